@@ -0,0 +1,21 @@
+//! A library source that moves red, green and blue along the zones, one zone a second.
+//! Build it with `cargo build -p predator-ng-plugin --example rotate` and copy the `.so` to the plugins directory.
+
+use std::time::Duration;
+
+use predator_ng_plugin::{LightingSource, ZONES, ZoneColors, export_source};
+
+const COLORS: ZoneColors = [[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+
+struct Rotate;
+
+impl LightingSource for Rotate {
+    fn frame(&mut self, elapsed: Duration) -> ZoneColors {
+        let shift = elapsed.as_secs() as usize % ZONES;
+        let mut zones = COLORS;
+        zones.rotate_right(shift);
+        zones
+    }
+}
+
+export_source!(Rotate);
@@ -0,0 +1,73 @@
+//! The interface for lighting sources that live outside of predator-ng.
+//!
+//! A source is asked for the color of every zone once per frame while it's selected in the plugin mode.
+//! It can be a dynamic library that implements [`LightingSource`] and exports it with [`export_source!`],
+//! or any executable that answers a [`FrameRequest`] line on its stdin with a [`Frame`] line on its stdout,
+//! both as JSON.
+//!
+//! Sources go in `$XDG_CONFIG_HOME/predator-ng/plugins/`, `examples/rotate.rs` is a library source to start from.
+//! This crate only depends on serde, so plugins don't have to build predator-ng and its GUI.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+pub const ZONES: usize = 3;
+
+/// Bumped whenever the functions exported by [`export_source!`] change, libraries built for another version aren't loaded
+pub const ABI_VERSION: u32 = 1;
+
+pub type ZoneColors = [[u8; 3]; ZONES];
+
+pub trait LightingSource {
+    /// The colors to show, `elapsed` is the time since the source was loaded
+    fn frame(&mut self, elapsed: Duration) -> ZoneColors;
+}
+
+/// What predator-ng writes to an executable source for every frame, as one line of JSON
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct FrameRequest {
+    pub elapsed_ms: u64
+}
+
+/// What an executable source answers with, as one line of JSON
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Frame {
+    pub zones: ZoneColors
+}
+
+/// Exports a [`LightingSource`] from a `cdylib`, made by evaluating `$source` when it's loaded.
+/// Only plain C types cross the library boundary, so the library and predator-ng don't need the same compiler.
+#[macro_export]
+macro_rules! export_source {
+    ($source:expr) => {
+        #[no_mangle]
+        pub extern "C" fn predator_ng_abi_version() -> u32 {
+            $crate::ABI_VERSION
+        }
+
+        #[no_mangle]
+        pub extern "C" fn predator_ng_source_new() -> *mut ::std::ffi::c_void {
+            let source: ::std::boxed::Box<dyn $crate::LightingSource> = ::std::boxed::Box::new($source);
+            ::std::boxed::Box::into_raw(::std::boxed::Box::new(source)) as *mut ::std::ffi::c_void
+        }
+
+        /// # Safety
+        /// `source` has to come from `predator_ng_source_new` and `colors` has to have room for 3 bytes per zone
+        #[no_mangle]
+        pub unsafe extern "C" fn predator_ng_source_frame(source: *mut ::std::ffi::c_void, elapsed_ms: u64, colors: *mut u8) {
+            let source = &mut *(source as *mut ::std::boxed::Box<dyn $crate::LightingSource>);
+            let zones = source.frame(::std::time::Duration::from_millis(elapsed_ms));
+            for (i, color) in zones.iter().enumerate() {
+                ::std::ptr::copy_nonoverlapping(color.as_ptr(), colors.add(i * 3), 3);
+            }
+        }
+
+        /// # Safety
+        /// `source` has to come from `predator_ng_source_new` and can't be used afterwards
+        #[no_mangle]
+        pub unsafe extern "C" fn predator_ng_source_free(source: *mut ::std::ffi::c_void) {
+            drop(::std::boxed::Box::from_raw(source as *mut ::std::boxed::Box<dyn $crate::LightingSource>));
+        }
+    };
+}
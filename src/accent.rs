@@ -0,0 +1,267 @@
+use std::{fs, sync::mpsc::{self, Sender}, thread::{self, JoinHandle}, time::Duration};
+
+use eframe::egui;
+use predator_ng::widgets::color_picker::parse_hex;
+use serde::{Deserialize, Serialize};
+use zbus::{blocking::{Connection, Proxy}, zvariant::{OwnedValue, Value}};
+
+use crate::{Config, KBLightMode, State, SharedState, apply_lighting, mqtt::parse_color, xdg_dir};
+
+const PORTAL_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const PORTAL_SETTINGS: &str = "org.freedesktop.portal.Settings";
+const ACCENT_NAMESPACE: &str = "org.freedesktop.appearance";
+const ACCENT_KEY: &str = "accent-color";
+const PYWAL_COLORS: usize = 16;
+
+// The portal announces changes to the accent color, but the files are small and simply read again this often
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum AccentSource {
+    /// The `accent-color` setting of the desktop portal, set by GNOME and others
+    Portal,
+    /// `AccentColor` in KDE's `kdeglobals`
+    Kde,
+    /// One of the colors in pywal's `colors.json`
+    Pywal
+}
+
+impl AccentSource {
+    const ALL: [AccentSource; 3] = [AccentSource::Portal, AccentSource::Kde, AccentSource::Pywal];
+
+    fn name(self) -> &'static str {
+        match self {
+            AccentSource::Portal => "Desktop portal (GNOME)",
+            AccentSource::Kde => "KDE",
+            AccentSource::Pywal => "pywal"
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum AccentTarget {
+    StaticZones,
+    DynamicColor
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AccentSettings {
+    pub enabled: bool,
+    pub source: AccentSource,
+    pub target: AccentTarget,
+    /// Which of pywal's colors is used, from 0 to 15
+    pub pywal_color: usize
+}
+
+impl Default for AccentSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            source: AccentSource::Portal,
+            target: AccentTarget::StaticZones,
+            pywal_color: 1
+        }
+    }
+}
+
+/// Unwraps the variant that the older `Read` method puts around the value
+fn portal_color(value: OwnedValue) -> Option<[u8; 3]> {
+    let value = match Value::from(value) {
+        Value::Value(inner) => *inner,
+        value => value
+    };
+    let (r, g, b) = <(f64, f64, f64)>::try_from(value).ok()?;
+
+    // Out of range when no accent color is set
+    [r, g, b].iter().all(|channel| (0.0..=1.0).contains(channel))
+        .then(|| [r, g, b].map(|channel| (channel * 255.0).round() as u8))
+}
+
+fn read_portal(bus: &Connection) -> Option<[u8; 3]> {
+    let arguments = (ACCENT_NAMESPACE, ACCENT_KEY);
+    let reply = bus.call_method(Some(PORTAL_NAME), PORTAL_PATH, Some(PORTAL_SETTINGS), "ReadOne", &arguments)
+        .or_else(|_| bus.call_method(Some(PORTAL_NAME), PORTAL_PATH, Some(PORTAL_SETTINGS), "Read", &arguments))
+        .ok()?;
+
+    portal_color(reply.body::<OwnedValue>().ok()?)
+}
+
+/// Sends the portal's accent color on `colors`, and again every time it changes, until the session bus goes away
+fn watch_portal(colors: Sender<Option<[u8; 3]>>) -> zbus::Result<()> {
+    let bus = Connection::session()?;
+    let portal = Proxy::new(&bus, PORTAL_NAME, PORTAL_PATH, PORTAL_SETTINGS)?;
+    // Subscribed to before reading, so that no change is missed in between
+    let changes = portal.receive_signal_with_args("SettingChanged", &[(0, ACCENT_NAMESPACE), (1, ACCENT_KEY)])?;
+
+    if colors.send(read_portal(&bus)).is_err() {
+        return Ok(());
+    }
+    for change in changes {
+        let (_, _, value): (String, String, OwnedValue) = change.body()?;
+        if colors.send(portal_color(value)).is_err() {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+/// `AccentColor` from the `[General]` group, or the selection color from before KDE had accent colors
+fn parse_kdeglobals(kdeglobals: &str) -> Option<[u8; 3]> {
+    let mut group = "";
+    let mut accent = None;
+    let mut selection = None;
+    for line in kdeglobals.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            group = name;
+        } else if let Some((key, value)) = line.split_once('=') {
+            match (group, key.trim()) {
+                ("General", "AccentColor") => accent = parse_color(value),
+                ("Colors:Selection", "BackgroundNormal") => selection = parse_color(value),
+                _ => {}
+            }
+        }
+    }
+
+    accent.or(selection)
+}
+
+fn read_kde() -> Option<[u8; 3]> {
+    parse_kdeglobals(&fs::read_to_string(xdg_dir("XDG_CONFIG_HOME", ".config")?.join("kdeglobals")).ok()?)
+}
+
+fn read_pywal(index: usize) -> Option<[u8; 3]> {
+    let path = xdg_dir("XDG_CACHE_HOME", ".cache")?.join("wal").join("colors.json");
+    let colors: serde_json::Value = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+
+    parse_hex(colors["colors"][format!("color{}", index)].as_str()?)
+}
+
+/// Gives the zones or the dynamic color the accent color, showing it if that's what the keyboard shows now
+fn apply(state: &SharedState, target: AccentTarget, color: [u8; 3]) {
+    let mut state = state.lock().unwrap();
+    let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+
+    let shown = match target {
+        AccentTarget::StaticZones => {
+            for zone in &mut cfg.kb.zones {
+                zone.color = color;
+            }
+            cfg.kb.mode == KBLightMode::Static
+        }
+        AccentTarget::DynamicColor => {
+            cfg.kb.color = color;
+            cfg.kb.mode == KBLightMode::Dynamic
+        }
+    };
+    if shown {
+        apply_lighting(static_dev, dynamic_dev, cfg);
+    }
+}
+
+pub fn spawn(state: SharedState) {
+    thread::spawn(move || {
+        let (portal_tx, portal_colors) = mpsc::channel();
+        let mut portal: Option<JoinHandle<()>> = None;
+        let mut portal_color = None;
+        let mut last_applied = None;
+
+        loop {
+            let settings = state.lock().unwrap().cfg.accent.clone();
+
+            if !settings.enabled {
+                // Forget the last color so that enabling the sync applies it right away
+                last_applied = None;
+            } else {
+                let color = match settings.source {
+                    AccentSource::Portal => {
+                        // Watched again if the session bus went away
+                        if portal.as_ref().is_none_or(JoinHandle::is_finished) {
+                            let portal_tx = portal_tx.clone();
+                            portal = Some(thread::spawn(move || {
+                                let _ = watch_portal(portal_tx);
+                            }));
+                        }
+                        portal_color
+                    }
+                    AccentSource::Kde => read_kde(),
+                    AccentSource::Pywal => read_pywal(settings.pywal_color)
+                };
+                let applied = color.map(|color| (color, settings.target));
+                if applied != last_applied {
+                    if let Some((color, target)) = applied {
+                        apply(&state, target, color);
+                    }
+                    last_applied = applied;
+                }
+            }
+
+            if let Ok(color) = portal_colors.recv_timeout(POLL_INTERVAL) {
+                portal_color = color;
+            }
+        }
+    });
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config) -> bool {
+    let settings = &mut cfg.accent;
+
+    let mut changed = ui.checkbox(&mut settings.enabled, "Follow the desktop's accent color").changed();
+    ui.add_enabled_ui(settings.enabled, |ui| {
+        egui::Grid::new("Accent").num_columns(2).show(ui, |ui| {
+            ui.label("Source");
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("AccentSource")
+                    .selected_text(settings.source.name())
+                    .show_ui(ui, |ui| {
+                        for source in AccentSource::ALL {
+                            changed |= ui.selectable_value(&mut settings.source, source, source.name()).changed();
+                        }
+                    });
+                if settings.source == AccentSource::Pywal {
+                    ui.label("Color");
+                    changed |= ui.add(egui::DragValue::new(&mut settings.pywal_color).clamp_range(0..=PYWAL_COLORS - 1)).changed();
+                }
+            });
+            ui.end_row();
+
+            ui.label("Apply to");
+            ui.horizontal(|ui| {
+                changed |= ui.radio_value(&mut settings.target, AccentTarget::StaticZones, "Static zones").changed();
+                changed |= ui.radio_value(&mut settings.target, AccentTarget::DynamicColor, "Dynamic color").changed();
+            });
+            ui.end_row();
+        });
+    });
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_kde_accent_colors() {
+        let kdeglobals = "[Colors:Selection]\nBackgroundNormal=61,174,233\n\n[General]\nAccentColor=233,100,60\nColorScheme=BreezeDark\n";
+        assert_eq!(parse_kdeglobals(kdeglobals), Some([233, 100, 60]));
+
+        // Before KDE had accent colors
+        assert_eq!(parse_kdeglobals("[Colors:Selection]\nBackgroundNormal = 61, 174, 233\n"), Some([61, 174, 233]));
+        // Only the key in the right group counts
+        assert_eq!(parse_kdeglobals("[Colors:View]\nAccentColor=1,2,3\n"), None);
+    }
+
+    #[test]
+    fn reads_portal_colors() {
+        let color = || Value::from((0.2, 0.4, 1.0));
+        assert_eq!(portal_color(OwnedValue::from(color())), Some([51, 102, 255]));
+        // As the older Read method returns it
+        assert_eq!(portal_color(OwnedValue::from(Value::Value(Box::new(color())))), Some([51, 102, 255]));
+        // No accent color set
+        assert_eq!(portal_color(OwnedValue::from(Value::from((-1.0, -1.0, -1.0)))), None);
+        assert_eq!(portal_color(OwnedValue::from(Value::from("blue"))), None);
+    }
+}
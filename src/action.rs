@@ -0,0 +1,90 @@
+use std::fs;
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{Profile, SharedState, fade, profile_picker};
+
+const PLATFORM_PROFILE: &str = "/sys/firmware/acpi/platform_profile";
+const PLATFORM_PROFILE_CHOICES: &str = "/sys/firmware/acpi/platform_profile_choices";
+
+/// What an automatic rule does once it's triggered. Anything left as `None` is kept unchanged.
+#[derive(Default, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct Action {
+    pub profile: Option<String>,
+    pub brightness: Option<u8>,
+    pub platform_profile: Option<String>
+}
+
+pub fn platform_profile_choices() -> Vec<String> {
+    fs::read_to_string(PLATFORM_PROFILE_CHOICES)
+        .map(|choices| choices.split_whitespace().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+pub fn platform_profile() -> Option<String> {
+    fs::read_to_string(PLATFORM_PROFILE).ok().map(|profile| profile.trim().to_string())
+}
+
+pub fn set_platform_profile(profile: &str) {
+    if let Err(e) = fs::write(PLATFORM_PROFILE, profile) {
+        eprintln!("[ERROR]: Could not set platform profile to {}: {}", profile, e);
+    }
+}
+
+pub fn apply(state: &SharedState, action: &Action) {
+    let profile = {
+        let state = state.lock().unwrap();
+        action.profile.as_deref().and_then(|name| state.cfg.profile(name)).map(|profile| profile.kb)
+    };
+
+    if let Some(mut kb) = profile {
+        if let Some(brightness) = action.brightness {
+            kb.brightness = brightness;
+        }
+        fade::transition(state, kb);
+    } else if let Some(brightness) = action.brightness {
+        fade::fade_brightness(state, brightness);
+    }
+
+    if let Some(platform_profile) = &action.platform_profile {
+        set_platform_profile(platform_profile);
+    }
+}
+
+pub fn show(ui: &mut egui::Ui, id: &str, profiles: &[Profile], choices: &[String], step: u8, action: &mut Action) -> bool {
+    let mut changed = false;
+
+    egui::Grid::new(id).num_columns(2).show(ui, |ui| {
+        ui.label("Profile");
+        changed |= profile_picker(ui, &format!("{}-profile", id), profiles, &mut action.profile);
+        ui.end_row();
+
+        ui.label("Brightness");
+        ui.horizontal(|ui| {
+            let mut override_brightness = action.brightness.is_some();
+            if ui.checkbox(&mut override_brightness, "").changed() {
+                action.brightness = override_brightness.then_some(100);
+                changed = true;
+            }
+            if let Some(brightness) = &mut action.brightness {
+                changed |= ui.add(egui::Slider::new(brightness, 0..=100).step_by(step as f64)).changed();
+            }
+        });
+        ui.end_row();
+
+        ui.label("Platform Profile");
+        egui::ComboBox::from_id_source(format!("{}-platform", id))
+            .selected_text(action.platform_profile.as_deref().unwrap_or("Unchanged"))
+            .show_ui(ui, |ui| {
+                changed |= ui.selectable_value(&mut action.platform_profile, None, "Unchanged").changed();
+                for choice in choices {
+                    changed |= ui.selectable_value(&mut action.platform_profile, Some(choice.clone()), choice).changed();
+                }
+            });
+        ui.end_row();
+    });
+
+    changed
+}
@@ -0,0 +1,333 @@
+use std::{io::Read, path::{Path, PathBuf}, thread};
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{Config, KBDynamicDirection, KBDynamicEffect, KBLightMode, State, SharedState, Zone, apply_lighting, change_brightness, control};
+
+// Far more than any request needs, the API can be opened to the network
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ApiSettings {
+    pub enabled: bool,
+    /// Set to 0.0.0.0 to allow other devices on the network
+    pub address: String,
+    pub port: u16,
+    /// Required as a bearer token when not empty
+    pub token: String
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1".to_string(),
+            port: 8642,
+            token: String::new()
+        }
+    }
+}
+
+/// The body of `PUT /kb/dynamic`, leaving out a field keeps its current value
+#[derive(Deserialize)]
+struct DynamicUpdate {
+    effect: Option<KBDynamicEffect>,
+    speed: Option<u8>,
+    direction: Option<KBDynamicDirection>,
+    color: Option<[u8; 3]>
+}
+
+type Reply = Result<(u16, String), (u16, String)>;
+
+fn json<T: Serialize>(status: u16, value: &T) -> Reply {
+    Ok((status, serde_json::to_string(value).unwrap()))
+}
+
+fn parse<'a, T: Deserialize<'a>>(body: &'a str) -> Result<T, (u16, String)> {
+    serde_json::from_str(body).map_err(|e| (400, e.to_string()))
+}
+
+/// Decodes the `%XX` escapes in a path segment or URI, such as the spaces in profile names
+pub fn percent_decode(segment: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = segment.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail.get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(decoded) if byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// Changes the lighting under the lock, shows it and saves it, replying with the new lighting
+fn update(state: &SharedState, config_path: &Path, change: impl FnOnce(&mut Config)) -> Reply {
+    let mut state = state.lock().unwrap();
+    let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+
+    change(cfg);
+    apply_lighting(static_dev, dynamic_dev, cfg);
+    let _ = state.store(config_path);
+
+    json(200, &state.cfg.kb)
+}
+
+fn route(state: &SharedState, config_path: &Path, method: &Method, path: &str, body: &str) -> Reply {
+    let segments: Vec<String> = path.trim_matches('/').split('/').map(percent_decode).collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    match (method, segments.as_slice()) {
+        (Method::Get, ["state"]) => json(200, &state.lock().unwrap().cfg.kb),
+        (Method::Get, ["profiles"]) => json(200, &state.lock().unwrap().cfg.profiles),
+        (Method::Put, ["kb", "mode"]) => {
+            let mode: KBLightMode = parse(body)?;
+            update(state, config_path, |cfg| cfg.kb.mode = mode)
+        }
+        (Method::Put, ["kb", "brightness"]) => {
+            let brightness: u8 = parse(body)?;
+            if brightness > 100 {
+                return Err((400, "The brightness goes from 0 to 100".to_string()));
+            }
+
+            let mut state = state.lock().unwrap();
+            let State { cfg, dynamic_dev, .. } = &mut *state;
+            cfg.kb.brightness = control::snap_brightness(cfg, brightness);
+            change_brightness(dynamic_dev, cfg);
+            let _ = state.store(config_path);
+
+            json(200, &state.cfg.kb)
+        }
+        (Method::Put, ["kb", "static", "zones", zone]) => {
+            let zone = match zone.parse::<usize>() {
+                Ok(zone @ 1..=3) => zone,
+                _ => return Err((404, format!("There's no zone {}", zone)))
+            };
+            let new_zone: Zone = parse(body)?;
+
+            update(state, config_path, |cfg| {
+                cfg.kb.mode = KBLightMode::Static;
+                cfg.kb.zones[zone - 1] = new_zone;
+            })
+        }
+        (Method::Put, ["kb", "dynamic"]) => {
+            let changes: DynamicUpdate = parse(body)?;
+            let (effect, capabilities) = {
+                let cfg = &state.lock().unwrap().cfg;
+                let effect = changes.effect.unwrap_or(cfg.kb.effect);
+                (effect, cfg.effects.capabilities(effect))
+            };
+            capabilities.check(effect, changes.speed, changes.direction.is_some(), changes.color.is_some())
+                .map_err(|e| (400, e))?;
+
+            update(state, config_path, |cfg| {
+                cfg.kb.mode = KBLightMode::Dynamic;
+                cfg.kb.effect = effect;
+                cfg.kb.speed = capabilities.clamp_speed(changes.speed.unwrap_or(cfg.kb.speed));
+                cfg.kb.direction = changes.direction.unwrap_or(cfg.kb.direction);
+                cfg.kb.color = changes.color.unwrap_or(cfg.kb.color);
+            })
+        }
+        (Method::Post, ["profiles", name, "apply"]) => {
+            let profile = state.lock().unwrap().cfg.profile(name).cloned();
+            let Some(profile) = profile else {
+                return Err((404, format!("No profile named {}", name)));
+            };
+
+            // The transition takes a while, so it's only started here
+            let state = state.clone();
+            let config_path = config_path.to_path_buf();
+            thread::spawn(move || {
+                control::apply_profile(&state, &profile.name);
+                control::save(&state, &config_path);
+            });
+
+            json(202, &profile.kb)
+        }
+        (_, ["state" | "profiles"]) | (_, ["kb", ..]) | (_, ["profiles", _, "apply"]) => Err((405, "Method not allowed".to_string())),
+        _ => Err((404, "Not found".to_string()))
+    }
+}
+
+fn authorized(request: &Request, token: &str) -> bool {
+    token.is_empty() || request.headers().iter().any(|header| {
+        header.field.equiv("Authorization") && header.value.as_str() == format!("Bearer {}", token)
+    })
+}
+
+fn handle(mut request: Request, state: &SharedState, config_path: &Path, token: &str) {
+    let reply = if authorized(&request, token) {
+        let mut body = String::new();
+        match request.as_reader().take(MAX_BODY_SIZE + 1).read_to_string(&mut body) {
+            Ok(size) if size as u64 > MAX_BODY_SIZE => Err((413, "The body is too large".to_string())),
+            Ok(_) => {
+                let path = request.url().split('?').next().unwrap_or_default().to_string();
+                route(state, config_path, request.method(), &path, &body)
+            }
+            Err(e) => Err((400, e.to_string()))
+        }
+    } else {
+        Err((401, "Missing or wrong token".to_string()))
+    };
+
+    let (status, body) = reply.unwrap_or_else(|(status, error)| (status, serde_json::json!({ "error": error }).to_string()));
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let response = Response::from_string(body).with_status_code(status).with_header(content_type);
+
+    if let Err(e) = request.respond(response) {
+        eprintln!("[ERROR]: Could not answer an API request: {}", e);
+    }
+}
+
+/// Starts the HTTP API if it's enabled
+pub fn spawn(state: SharedState, config_path: PathBuf) {
+    let settings = state.lock().unwrap().cfg.api.clone();
+    if !settings.enabled {
+        return;
+    }
+
+    match Server::http((settings.address.as_str(), settings.port)) {
+        Ok(server) => {
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(request, &state, &config_path, &settings.token);
+                }
+            });
+        }
+        Err(e) => eprintln!("[ERROR]: Could not start the API on {}:{}: {}", settings.address, settings.port, e)
+    }
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config) -> bool {
+    let settings = &mut cfg.api;
+
+    let mut changed = ui.checkbox(&mut settings.enabled, "Allow controlling the keyboard over HTTP")
+        .on_hover_text("Takes effect after restarting predator-ng")
+        .changed();
+    ui.add_enabled_ui(settings.enabled, |ui| {
+        egui::Grid::new("Api").num_columns(2).show(ui, |ui| {
+            ui.label("Address");
+            ui.horizontal(|ui| {
+                changed |= ui.text_edit_singleline(&mut settings.address)
+                    .on_hover_text("Use 0.0.0.0 to allow other devices on the network")
+                    .changed();
+                changed |= ui.add(egui::DragValue::new(&mut settings.port).clamp_range(1024..=65535)).changed();
+            });
+            ui.end_row();
+
+            ui.label("Token");
+            changed |= ui.add(egui::TextEdit::singleline(&mut settings.token).password(true).hint_text("None"))
+                .on_hover_text("Requests then need an \"Authorization: Bearer <token>\" header")
+                .changed();
+            ui.end_row();
+        });
+    });
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, net::TcpStream};
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{KBLighting, Profile, testing::{test_config_path, test_state}};
+
+    fn request(state: &SharedState, method: Method, path: &str, body: &str) -> (u16, Value) {
+        let (status, body) = route(state, &test_config_path("api"), &method, path, body).unwrap_or_else(|error| error);
+        (status, serde_json::from_str(&body).unwrap_or(Value::String(body)))
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(percent_decode("My%20Profile"), "My Profile");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        // Not escapes, so they're kept
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%2"), "%zz%2");
+    }
+
+    #[test]
+    fn reads_and_changes_the_lighting() {
+        let state = test_state("api");
+
+        let (status, kb) = request(&state, Method::Get, "/state", "");
+        assert_eq!(status, 200);
+        assert_eq!(kb["mode"], "Static");
+
+        let (status, kb) = request(&state, Method::Put, "/kb/brightness", "60");
+        assert_eq!((status, &kb["brightness"]), (200, &Value::from(50)));
+        assert_eq!(request(&state, Method::Put, "/kb/brightness", "101").0, 400);
+        assert_eq!(request(&state, Method::Put, "/kb/brightness", "bright").0, 400);
+
+        let (status, kb) = request(&state, Method::Put, "/kb/static/zones/2", r#"{"color": [1, 2, 3], "enabled": false}"#);
+        assert_eq!(status, 200);
+        assert_eq!(kb["zones"][1], serde_json::json!({ "color": [1, 2, 3], "enabled": false }));
+        assert_eq!(request(&state, Method::Put, "/kb/static/zones/4", r#"{"color": [1, 2, 3], "enabled": true}"#).0, 404);
+
+        let (status, kb) = request(&state, Method::Put, "/kb/dynamic", r#"{"effect": "Wave", "speed": 3}"#);
+        assert_eq!(status, 200);
+        assert_eq!((&kb["mode"], &kb["effect"], &kb["speed"]), (&Value::from("Dynamic"), &Value::from("Wave"), &Value::from(3)));
+        // Wave chooses its own colors
+        assert_eq!(request(&state, Method::Put, "/kb/dynamic", r#"{"color": [1, 2, 3]}"#).0, 400);
+        assert_eq!(request(&state, Method::Put, "/kb/dynamic", r#"{"speed": 0}"#).0, 400);
+    }
+
+    #[test]
+    fn applies_profiles_by_name() {
+        let state = test_state("api-profiles");
+        let kb = KBLighting { brightness: 75, ..Default::default() };
+        state.lock().unwrap().cfg.profiles.push(Profile { name: "Late Night".to_string(), kb });
+
+        let (status, applied) = request(&state, Method::Get, "/profiles", "");
+        assert_eq!((status, &applied[0]["name"]), (200, &Value::from("Late Night")));
+
+        let (status, applied) = request(&state, Method::Post, "/profiles/Late%20Night/apply", "");
+        assert_eq!((status, &applied["brightness"]), (202, &Value::from(75)));
+        assert_eq!(request(&state, Method::Post, "/profiles/Early/apply", "").0, 404);
+    }
+
+    #[test]
+    fn rejects_unknown_requests() {
+        let state = test_state("api-unknown");
+        assert_eq!(request(&state, Method::Delete, "/state", "").0, 405);
+        assert_eq!(request(&state, Method::Get, "/kb/brightness", "").0, 405);
+        assert_eq!(request(&state, Method::Get, "/lights", "").0, 404);
+    }
+
+    #[test]
+    fn rejects_large_bodies() {
+        let state = test_state("api-large");
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        let serving = thread::spawn(move || {
+            let request = server.recv().unwrap();
+            handle(request, &state, &test_config_path("api-large"), "");
+        });
+
+        let body = " ".repeat(MAX_BODY_SIZE as usize + 1);
+        let mut stream = TcpStream::connect(address).unwrap();
+        let head = format!("PUT /kb/brightness HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+        stream.write_all((head + body.as_str()).as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+        serving.join().unwrap();
+    }
+}
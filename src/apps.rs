@@ -0,0 +1,281 @@
+use std::{fs, process::Command, thread, time::Duration};
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, KBLighting, SharedState, action::{self, Action}, fade};
+
+const PROC_DIR: &str = "/proc";
+// The kernel cuts process names down to this many bytes
+const COMM_LENGTH: usize = 15;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum MatchTarget {
+    /// The process name, as in `/proc/<pid>/comm`
+    Process,
+    /// Anywhere in the command line, as in `/proc/<pid>/cmdline`
+    CommandLine,
+    /// The class of the focused window, only available on X11
+    Window
+}
+
+impl MatchTarget {
+    const ALL: [MatchTarget; 3] = [MatchTarget::Process, MatchTarget::CommandLine, MatchTarget::Window];
+
+    fn name(self) -> &'static str {
+        match self {
+            MatchTarget::Process => "Process name",
+            MatchTarget::CommandLine => "Command line",
+            MatchTarget::Window => "Window class"
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AppRule {
+    pub name: String,
+    pub target: MatchTarget,
+    pub pattern: String,
+    pub action: Action
+}
+
+impl Default for AppRule {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            target: MatchTarget::Process,
+            pattern: String::new(),
+            action: Default::default()
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AppRules {
+    pub enabled: bool,
+    pub rules: Vec<AppRule>
+}
+
+/// What was running during the last scan, only read for the targets some rule needs
+#[derive(Default)]
+struct Running {
+    processes: Vec<String>,
+    command_lines: Vec<String>,
+    window_class: Option<String>
+}
+
+impl Running {
+    fn scan(rules: &[AppRule]) -> Self {
+        let needs = |target| rules.iter().any(|rule| rule.target == target && !rule.pattern.is_empty());
+        let mut running = Running::default();
+
+        if needs(MatchTarget::Process) || needs(MatchTarget::CommandLine) {
+            for entry in fs::read_dir(PROC_DIR).into_iter().flatten().flatten() {
+                if !entry.file_name().to_string_lossy().bytes().all(|byte| byte.is_ascii_digit()) {
+                    continue;
+                }
+
+                if let Ok(comm) = fs::read_to_string(entry.path().join("comm")) {
+                    running.processes.push(comm.trim_end().to_lowercase());
+                }
+                if let Ok(cmdline) = fs::read(entry.path().join("cmdline")) {
+                    // Arguments are separated by NUL bytes
+                    let cmdline = String::from_utf8_lossy(&cmdline).replace('\0', " ");
+                    running.command_lines.push(cmdline.trim_end().to_lowercase());
+                }
+            }
+        }
+        if needs(MatchTarget::Window) {
+            running.window_class = focused_window_class().map(|class| class.to_lowercase());
+        }
+
+        running
+    }
+
+    fn matches(&self, rule: &AppRule) -> bool {
+        let pattern = rule.pattern.to_lowercase();
+        if pattern.is_empty() {
+            return false;
+        }
+
+        match rule.target {
+            MatchTarget::Process => {
+                let comm = pattern.char_indices()
+                    .take_while(|(i, c)| i + c.len_utf8() <= COMM_LENGTH)
+                    .map(|(_, c)| c)
+                    .collect::<String>();
+                self.processes.contains(&comm)
+            }
+            MatchTarget::CommandLine => self.command_lines.iter().any(|cmdline| cmdline.contains(&pattern)),
+            MatchTarget::Window => self.window_class.as_ref().is_some_and(|class| class.contains(&pattern))
+        }
+    }
+}
+
+fn xprop(args: &[&str]) -> Option<String> {
+    let output = Command::new("xprop").args(args).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Both parts of the focused window's `WM_CLASS`, such as `"steam", "Steam"`
+fn focused_window_class() -> Option<String> {
+    // _NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007
+    let active = xprop(&["-root", "_NET_ACTIVE_WINDOW"])?;
+    let id = active.split_whitespace().last()?;
+    // WM_CLASS(STRING) = "steam", "Steam"
+    let class = xprop(&["-id", id, "WM_CLASS"])?;
+
+    Some(class.split_once('=')?.1.trim().to_string())
+}
+
+/// The lighting and platform profile from before the first rule was applied
+struct Saved {
+    kb: KBLighting,
+    platform_profile: Option<String>
+}
+
+fn revert(state: &SharedState, saved: Saved) {
+    if state.lock().unwrap().cfg.kb != saved.kb {
+        fade::transition(state, saved.kb);
+    }
+    if let Some(platform_profile) = saved.platform_profile {
+        if action::platform_profile().as_ref() != Some(&platform_profile) {
+            action::set_platform_profile(&platform_profile);
+        }
+    }
+}
+
+pub fn spawn(state: SharedState) {
+    thread::spawn(move || {
+        let mut last_active = None;
+        let mut saved: Option<Saved> = None;
+
+        loop {
+            let apps = state.lock().unwrap().cfg.apps.clone();
+
+            let active = if apps.enabled {
+                let running = Running::scan(&apps.rules);
+                apps.rules.iter().position(|rule| running.matches(rule))
+            } else {
+                None
+            };
+
+            if active != last_active {
+                match active {
+                    Some(i) => {
+                        if saved.is_none() {
+                            let kb = state.lock().unwrap().cfg.kb;
+                            saved = Some(Saved { kb, platform_profile: action::platform_profile() });
+                        }
+                        action::apply(&state, &apps.rules[i].action);
+                    }
+                    None => {
+                        if let Some(saved) = saved.take() {
+                            revert(&state, saved);
+                        }
+                    }
+                }
+                last_active = active;
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config) -> bool {
+    let choices = action::platform_profile_choices();
+    let step = cfg.fade.step();
+    let Config { profiles, apps, .. } = cfg;
+
+    let mut changed = ui.checkbox(&mut apps.enabled, "Change the lighting while certain apps are running")
+        .on_hover_text("The first matching app wins, everything is reverted once none of them are running")
+        .changed();
+    ui.add_enabled_ui(apps.enabled, |ui| {
+        let mut removed = None;
+        for (i, rule) in apps.rules.iter_mut().enumerate() {
+            ui.separator();
+            ui.horizontal(|ui| {
+                changed |= ui.add(egui::TextEdit::singleline(&mut rule.name).hint_text("Name").desired_width(100.0)).changed();
+                egui::ComboBox::from_id_source(format!("AppTarget{}", i))
+                    .selected_text(rule.target.name())
+                    .show_ui(ui, |ui| {
+                        for target in MatchTarget::ALL {
+                            changed |= ui.selectable_value(&mut rule.target, target, target.name()).changed();
+                        }
+                    });
+                let hint = match rule.target {
+                    MatchTarget::Process => "steam",
+                    MatchTarget::CommandLine => "SteamLaunch",
+                    MatchTarget::Window => "steam_app_"
+                };
+                changed |= ui.add(egui::TextEdit::singleline(&mut rule.pattern).hint_text(hint).desired_width(120.0)).changed();
+                if ui.button("Remove").clicked() {
+                    removed = Some(i);
+                }
+            });
+            changed |= action::show(ui, &format!("AppAction{}", i), profiles, &choices, step, &mut rule.action);
+        }
+        if let Some(i) = removed {
+            apps.rules.remove(i);
+            changed = true;
+        }
+
+        ui.separator();
+        if ui.button("Add app").clicked() {
+            apps.rules.push(Default::default());
+            changed = true;
+        }
+    });
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(target: MatchTarget, pattern: &str) -> AppRule {
+        AppRule { target, pattern: pattern.to_string(), ..Default::default() }
+    }
+
+    fn running() -> Running {
+        Running {
+            processes: vec!["firefox".to_string(), "gamescope-wl-lo".to_string()],
+            command_lines: vec!["/usr/bin/firefox --new-window".to_string(), "/opt/games/my game/game.x86_64 --fullscreen".to_string()],
+            window_class: Some("\"steam\", \"steam\"".to_string())
+        }
+    }
+
+    #[test]
+    fn matches_process_names() {
+        let running = running();
+        assert!(running.matches(&rule(MatchTarget::Process, "Firefox")));
+        // The whole name has to match
+        assert!(!running.matches(&rule(MatchTarget::Process, "fire")));
+        // The kernel only keeps the start of long names
+        assert!(running.matches(&rule(MatchTarget::Process, "gamescope-wl-long-name")));
+        assert!(!running.matches(&rule(MatchTarget::Process, "")));
+    }
+
+    #[test]
+    fn matches_full_paths() {
+        let running = running();
+        assert!(running.matches(&rule(MatchTarget::CommandLine, "/opt/games/My Game/game.x86_64")));
+        assert!(running.matches(&rule(MatchTarget::CommandLine, "--new-window")));
+        assert!(!running.matches(&rule(MatchTarget::CommandLine, "/usr/bin/steam")));
+        assert!(running.matches(&rule(MatchTarget::Window, "Steam")));
+        assert!(!Running::default().matches(&rule(MatchTarget::Window, "Steam")));
+    }
+
+    #[test]
+    fn finds_running_processes() {
+        let path = std::env::current_exe().unwrap().display().to_string();
+        let rules = [rule(MatchTarget::CommandLine, &path)];
+        assert!(Running::scan(&rules).matches(&rules[0]));
+    }
+}
@@ -0,0 +1,317 @@
+use std::{fs::File, sync::Mutex};
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, KBLightMode, Zone, apply_lighting};
+
+// What the screen and the keyboard are compared on in each step of the wizard
+const WHITE: [u8; 3] = [255, 255, 255];
+const GREY: [u8; 3] = [128, 128, 128];
+const CHECK_COLORS: [(&str, [u8; 3]); 6] = [
+    ("Red", [255, 0, 0]),
+    ("Orange", [255, 165, 0]),
+    ("Yellow", [255, 255, 0]),
+    ("Green", [0, 255, 0]),
+    ("Blue", [0, 0, 255]),
+    ("Violet", [148, 0, 211])
+];
+
+// The wizard in progress, None when it isn't running
+static WIZARD: Mutex<Option<Wizard>> = Mutex::new(None);
+
+/// Corrects the colors of one zone so they look like they do on the screen
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ZoneCalibration {
+    /// Scales the red, green and blue channels, so a channel that's too strong can be toned down
+    pub gain: [f32; 3],
+    /// Applied to every channel before the gain, above 1 darkens the mid tones
+    pub gamma: f32
+}
+
+impl Default for ZoneCalibration {
+    fn default() -> Self {
+        Self {
+            gain: [1.0; 3],
+            gamma: 1.0
+        }
+    }
+}
+
+impl ZoneCalibration {
+    fn apply(&self, color: [u8; 3]) -> [u8; 3] {
+        let mut calibrated = [0; 3];
+        for (channel, (value, gain)) in calibrated.iter_mut().zip(color.into_iter().zip(self.gain)) {
+            *channel = ((value as f32 / 255.0).powf(self.gamma) * gain * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+        calibrated
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct CalibrationSettings {
+    pub enabled: bool,
+    pub zones: [ZoneCalibration; 3]
+}
+
+impl CalibrationSettings {
+    /// The color to write to `zone`, starting from 1
+    pub fn zone(&self, zone: usize, color: [u8; 3]) -> [u8; 3] {
+        if self.enabled { self.zones[zone - 1].apply(color) } else { color }
+    }
+
+    /// The color to write for the dynamic effects, which light every zone with the same one
+    pub fn dynamic(&self, color: [u8; 3]) -> [u8; 3] {
+        if !self.enabled {
+            return color;
+        }
+
+        let count = self.zones.len() as f32;
+        let mut average = ZoneCalibration { gain: [0.0; 3], gamma: 0.0 };
+        for zone in &self.zones {
+            for (gain, zone_gain) in average.gain.iter_mut().zip(zone.gain) {
+                *gain += zone_gain / count;
+            }
+            average.gamma += zone.gamma / count;
+        }
+        average.apply(color)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    WhiteBalance,
+    Gamma,
+    Check([u8; 3])
+}
+
+struct Wizard {
+    step: Step,
+    // Restored when the wizard is cancelled
+    previous: CalibrationSettings,
+    // Whether the keyboard shows the current step, so it's only written when something changes
+    shown: bool
+}
+
+/// Lights every zone with `color` through the calibration being edited
+fn show_test_color(static_dev: &mut File, dynamic_dev: &mut File, cfg: &Config, color: [u8; 3]) {
+    let mut test_cfg = cfg.clone();
+    test_cfg.kb.mode = KBLightMode::Static;
+    test_cfg.kb.zones = [Zone { color, enabled: true }; 3];
+    if test_cfg.kb.brightness == 0 {
+        test_cfg.kb.brightness = 100;
+    }
+    apply_lighting(static_dev, dynamic_dev, &test_cfg);
+}
+
+fn swatch(ui: &mut egui::Ui, color: [u8; 3]) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(60.0, 30.0), egui::Sense::hover());
+    ui.painter().rect_filled(rect, 3.0, egui::Color32::from_rgb(color[0], color[1], color[2]));
+}
+
+fn gain_sliders(ui: &mut egui::Ui, id: &str, zones: &mut [ZoneCalibration; 3]) -> bool {
+    let mut changed = false;
+
+    egui::Grid::new(id).num_columns(4).show(ui, |ui| {
+        ui.label("");
+        for zone in 1..=3 {
+            ui.label(format!("Zone {}", zone));
+        }
+        ui.end_row();
+        for (channel, name) in ["Red", "Green", "Blue"].into_iter().enumerate() {
+            ui.label(name);
+            for zone in zones.iter_mut() {
+                changed |= ui.add(egui::Slider::new(&mut zone.gain[channel], 0.0..=1.0).fixed_decimals(2)).changed();
+            }
+            ui.end_row();
+        }
+    });
+
+    changed
+}
+
+fn gamma_sliders(ui: &mut egui::Ui, id: &str, zones: &mut [ZoneCalibration; 3]) -> bool {
+    let mut changed = false;
+
+    egui::Grid::new(id).num_columns(4).show(ui, |ui| {
+        ui.label("Gamma");
+        for zone in zones.iter_mut() {
+            changed |= ui.add(egui::Slider::new(&mut zone.gamma, 0.5..=3.0).fixed_decimals(2)).changed();
+        }
+        ui.end_row();
+    });
+
+    changed
+}
+
+fn show_steps(ui: &mut egui::Ui, static_dev: &mut File, dynamic_dev: &mut File, cfg: &mut Config, wizard: &mut Wizard) -> Option<bool> {
+    let calibration = &mut cfg.calibration;
+    let mut finished = None;
+
+    match wizard.step {
+        Step::WhiteBalance => {
+            ui.label("Step 1 of 3: Every zone is lit white. Lower the channels that stand out until each zone looks neutral, like the swatch.");
+            swatch(ui, WHITE);
+            wizard.shown &= !gain_sliders(ui, "CalibrationWizardGain", &mut calibration.zones);
+        }
+        Step::Gamma => {
+            ui.label("Step 2 of 3: Every zone is lit grey. Adjust the gamma until each zone looks about as bright as the swatch, relative to white.");
+            ui.horizontal(|ui| {
+                swatch(ui, GREY);
+                swatch(ui, WHITE);
+            });
+            wizard.shown &= !gamma_sliders(ui, "CalibrationWizardGamma", &mut calibration.zones);
+        }
+        Step::Check(color) => {
+            ui.label("Step 3 of 3: Compare some colors with the screen, going back if they're off.");
+            ui.horizontal(|ui| {
+                for (name, check_color) in CHECK_COLORS {
+                    if ui.selectable_label(color == check_color, name).clicked() {
+                        wizard.step = Step::Check(check_color);
+                        wizard.shown = false;
+                    }
+                }
+            });
+            swatch(ui, color);
+        }
+    }
+
+    ui.horizontal(|ui| {
+        if ui.button("Cancel").clicked() {
+            *calibration = wizard.previous.clone();
+            finished = Some(false);
+        }
+        if wizard.step != Step::WhiteBalance && ui.button("Back").clicked() {
+            wizard.step = if wizard.step == Step::Gamma { Step::WhiteBalance } else { Step::Gamma };
+            wizard.shown = false;
+        }
+        match wizard.step {
+            Step::Check(_) => {
+                if ui.button("Finish").clicked() {
+                    finished = Some(true);
+                }
+            }
+            _ => {
+                if ui.button("Next").clicked() {
+                    wizard.step = if wizard.step == Step::WhiteBalance { Step::Gamma } else { Step::Check(CHECK_COLORS[0].1) };
+                    wizard.shown = false;
+                }
+            }
+        }
+    });
+
+    if finished.is_none() && !wizard.shown {
+        let color = match wizard.step {
+            Step::WhiteBalance => WHITE,
+            Step::Gamma => GREY,
+            Step::Check(color) => color
+        };
+        show_test_color(static_dev, dynamic_dev, cfg, color);
+        wizard.shown = true;
+    }
+
+    finished
+}
+
+/// Shows the wizard while it's running, walking through matching the white point, then the mid tones,
+/// then checking a few colors against the screen. Returns whether the calibration has to be saved.
+pub fn show_wizard(ctx: &egui::Context, static_dev: &mut File, dynamic_dev: &mut File, cfg: &mut Config) -> bool {
+    let mut wizard = WIZARD.lock().unwrap();
+    let Some(running) = wizard.as_mut() else {
+        return false;
+    };
+
+    let finished = egui::Window::new("Calibration")
+        .collapsible(false)
+        .show(ctx, |ui| show_steps(ui, static_dev, dynamic_dev, cfg, running))
+        .and_then(|window| window.inner)
+        .flatten();
+    let Some(save) = finished else {
+        return false;
+    };
+
+    *wizard = None;
+    apply_lighting(static_dev, dynamic_dev, cfg);
+    save
+}
+
+pub fn show_settings(ui: &mut egui::Ui, static_dev: &mut File, dynamic_dev: &mut File, cfg: &mut Config) -> bool {
+    let mut wizard = WIZARD.lock().unwrap();
+    let calibration = &mut cfg.calibration;
+    let mut changed = ui.checkbox(&mut calibration.enabled, "Correct the colors the keyboard shows").changed();
+    ui.add_enabled_ui(calibration.enabled && wizard.is_none(), |ui| {
+        changed |= gain_sliders(ui, "CalibrationGain", &mut calibration.zones);
+        changed |= gamma_sliders(ui, "CalibrationGamma", &mut calibration.zones);
+    });
+    ui.horizontal(|ui| {
+        if ui.add_enabled(wizard.is_none(), egui::Button::new("Calibrate...")).on_hover_text("Compare the keyboard with the screen step by step").clicked() {
+            *wizard = Some(Wizard { step: Step::WhiteBalance, previous: calibration.clone(), shown: false });
+            calibration.enabled = true;
+        }
+        if ui.add_enabled(calibration.zones != CalibrationSettings::default().zones, egui::Button::new("Reset")).clicked() {
+            calibration.zones = Default::default();
+            changed = true;
+        }
+    });
+
+    if changed {
+        apply_lighting(static_dev, dynamic_dev, cfg);
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLORS: [[u8; 3]; 4] = [[0, 0, 0], [255, 255, 255], [255, 128, 0], [12, 200, 99]];
+
+    #[test]
+    fn identity_keeps_colors() {
+        let mut settings = CalibrationSettings { enabled: true, ..Default::default() };
+        for color in COLORS {
+            assert_eq!(ZoneCalibration::default().apply(color), color);
+            assert_eq!(settings.zone(2, color), color);
+            assert_eq!(settings.dynamic(color), color);
+        }
+
+        // Turned off, even a calibration that changes colors keeps them
+        settings.enabled = false;
+        settings.zones[0].gain = [0.5; 3];
+        assert_eq!(settings.zone(1, [255, 128, 0]), [255, 128, 0]);
+        assert_eq!(settings.dynamic([255, 128, 0]), [255, 128, 0]);
+    }
+
+    #[test]
+    fn applies_gain_and_gamma() {
+        let warm = ZoneCalibration { gain: [1.0, 0.8, 0.5], gamma: 1.0 };
+        assert_eq!(warm.apply([255, 255, 255]), [255, 204, 128]);
+        assert_eq!(warm.apply([0, 0, 0]), [0, 0, 0]);
+
+        // Gamma darkens the mid tones, but keeps black and full channels
+        let dark = ZoneCalibration { gain: [1.0; 3], gamma: 2.0 };
+        assert_eq!(dark.apply([0, 128, 255]), [0, 64, 255]);
+
+        // A gain above 1 can't go past full
+        let strong = ZoneCalibration { gain: [2.0; 3], gamma: 1.0 };
+        assert_eq!(strong.apply([100, 200, 255]), [200, 255, 255]);
+    }
+
+    #[test]
+    fn dynamic_effects_use_the_average() {
+        let settings = CalibrationSettings {
+            enabled: true,
+            zones: [
+                ZoneCalibration { gain: [1.0, 0.6, 0.4], gamma: 1.0 },
+                ZoneCalibration { gain: [1.0, 0.9, 0.4], gamma: 1.0 },
+                ZoneCalibration { gain: [1.0, 0.9, 0.4], gamma: 1.0 }
+            ]
+        };
+        assert_eq!(settings.zone(1, [255, 255, 255]), [255, 153, 102]);
+        assert_eq!(settings.zone(3, [255, 255, 255]), [255, 230, 102]);
+        assert_eq!(settings.dynamic([255, 255, 255]), [255, 204, 102]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Conversions between sRGB colors, as the keyboard takes them, and the color spaces used to pick and blend them.
+//!
+//! Hues are in degrees, saturation, value and lightness go from 0 to 100.
+//! OKLab uses a lightness from 0 to 1, as in <https://bottosson.github.io/posts/oklab/>.
+
+fn to_unit(rgb: [u8; 3]) -> [f32; 3] {
+    rgb.map(|channel| channel as f32 / 255.0)
+}
+
+fn from_unit(rgb: [f32; 3]) -> [u8; 3] {
+    rgb.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+/// The hue in degrees, the chroma, and the largest and smallest channels
+fn hue_chroma(rgb: [f32; 3]) -> (f32, f32, f32, f32) {
+    let [r, g, b] = rgb;
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / chroma + 2.0)
+    } else {
+        60.0 * ((r - g) / chroma + 4.0)
+    };
+
+    (hue, chroma, max, min)
+}
+
+/// The color with the given hue and chroma, brightened by `m` on every channel
+fn from_hue_chroma(hue: f32, chroma: f32, m: f32) -> [u8; 3] {
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x)
+    };
+
+    from_unit([r + m, g + m, b + m])
+}
+
+pub fn rgb_to_hsv(rgb: [u8; 3]) -> [f32; 3] {
+    let (hue, chroma, max, _) = hue_chroma(to_unit(rgb));
+    let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+
+    [hue, saturation * 100.0, max * 100.0]
+}
+
+pub fn hsv_to_rgb([hue, saturation, value]: [f32; 3]) -> [u8; 3] {
+    let value = value / 100.0;
+    let chroma = value * saturation / 100.0;
+
+    from_hue_chroma(hue, chroma, value - chroma)
+}
+
+pub fn rgb_to_hsl(rgb: [u8; 3]) -> [f32; 3] {
+    let (hue, chroma, max, min) = hue_chroma(to_unit(rgb));
+    let lightness = (max + min) / 2.0;
+    let saturation = if chroma == 0.0 { 0.0 } else { chroma / (1.0 - (2.0 * lightness - 1.0).abs()) };
+
+    [hue, saturation * 100.0, lightness * 100.0]
+}
+
+pub fn hsl_to_rgb([hue, saturation, lightness]: [f32; 3]) -> [u8; 3] {
+    let lightness = lightness / 100.0;
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation / 100.0;
+
+    from_hue_chroma(hue, chroma, lightness - chroma / 2.0)
+}
+
+fn srgb_to_linear(channel: f32) -> f32 {
+    if channel <= 0.04045 { channel / 12.92 } else { ((channel + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(channel: f32) -> f32 {
+    if channel <= 0.0031308 { channel * 12.92 } else { 1.055 * channel.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// The channels as amounts of light from 0 to 1, which mix like light does
+pub fn rgb_to_linear(rgb: [u8; 3]) -> [f32; 3] {
+    to_unit(rgb).map(srgb_to_linear)
+}
+
+pub fn linear_to_rgb(linear: [f32; 3]) -> [u8; 3] {
+    from_unit(linear.map(|channel| linear_to_srgb(channel.max(0.0))))
+}
+
+pub fn rgb_to_oklab(rgb: [u8; 3]) -> [f32; 3] {
+    // The matrices have more precision than f32
+    let [r, g, b] = rgb_to_linear(rgb).map(|channel| channel as f64);
+
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        (0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s) as f32,
+        (1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s) as f32,
+        (0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s) as f32
+    ]
+}
+
+fn oklab_to_linear([lightness, a, b]: [f32; 3]) -> [f64; 3] {
+    let (lightness, a, b) = (lightness as f64, a as f64, b as f64);
+
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+    [
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s
+    ]
+}
+
+/// Colors outside of sRGB keep their lightness and hue, losing chroma until they fit
+pub fn oklab_to_rgb([lightness, a, b]: [f32; 3]) -> [u8; 3] {
+    let in_gamut = |linear: [f64; 3]| linear.iter().all(|channel| (-1e-4..=1.0001).contains(channel));
+
+    let mut linear = oklab_to_linear([lightness, a, b]);
+    if !in_gamut(linear) {
+        let (mut low, mut high) = (0.0, 1.0);
+        for _ in 0..20 {
+            let middle = (low + high) / 2.0;
+            if in_gamut(oklab_to_linear([lightness, a * middle, b * middle])) { low = middle } else { high = middle }
+        }
+        linear = oklab_to_linear([lightness, a * low, b * low]);
+    }
+
+    linear_to_rgb(linear.map(|channel| channel as f32))
+}
+
+/// OKLab as a lightness from 0 to 100, a chroma and a hue
+pub fn rgb_to_oklch(rgb: [u8; 3]) -> [f32; 3] {
+    let [lightness, a, b] = rgb_to_oklab(rgb);
+    let chroma = a.hypot(b);
+    // Greys have no hue, rounding errors would otherwise make one up
+    let hue = if chroma < 1e-4 { 0.0 } else { b.atan2(a).to_degrees().rem_euclid(360.0) };
+
+    [lightness * 100.0, chroma, hue]
+}
+
+pub fn oklch_to_rgb([lightness, chroma, hue]: [f32; 3]) -> [u8; 3] {
+    oklab_to_rgb([lightness / 100.0, chroma * hue.to_radians().cos(), chroma * hue.to_radians().sin()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3], tolerance: f32) {
+        assert!(actual.iter().zip(expected).all(|(actual, expected)| (actual - expected).abs() <= tolerance), "{:?} isn't {:?}", actual, expected);
+    }
+
+    // Every channel in steps of 51, 216 colors
+    fn samples() -> impl Iterator<Item = [u8; 3]> {
+        (0..216u32).map(|i| [i / 36, i / 6 % 6, i % 6].map(|step| (step * 51) as u8))
+    }
+
+    #[test]
+    fn converts_known_colors_to_oklab() {
+        // From https://bottosson.github.io/posts/oklab/ and the CSS Color 4 examples
+        assert_close(rgb_to_oklab([255, 255, 255]), [1.0, 0.0, 0.0], 1e-4);
+        assert_close(rgb_to_oklab([0, 0, 0]), [0.0, 0.0, 0.0], 1e-4);
+        assert_close(rgb_to_oklab([255, 0, 0]), [0.62796, 0.22486, 0.12585], 1e-4);
+        assert_close(rgb_to_oklab([0, 255, 0]), [0.86644, -0.23389, 0.17950], 1e-4);
+        assert_close(rgb_to_oklab([0, 0, 255]), [0.45201, -0.03246, -0.31153], 1e-4);
+    }
+
+    #[test]
+    fn converts_known_colors_from_oklab() {
+        assert_eq!(oklab_to_rgb([0.62796, 0.22486, 0.12585]), [255, 0, 0]);
+        assert_eq!(oklab_to_rgb([0.86644, -0.23389, 0.17950]), [0, 255, 0]);
+        assert_eq!(oklab_to_rgb([0.45201, -0.03246, -0.31153]), [0, 0, 255]);
+    }
+
+    #[test]
+    fn round_trips_through_oklab_and_oklch() {
+        for rgb in samples() {
+            assert_eq!(oklab_to_rgb(rgb_to_oklab(rgb)), rgb);
+            assert_eq!(oklch_to_rgb(rgb_to_oklch(rgb)), rgb);
+        }
+    }
+
+    #[test]
+    fn greys_have_no_hue() {
+        for level in [0, 1, 128, 254, 255] {
+            let [_, chroma, hue] = rgb_to_oklch([level; 3]);
+            assert!(chroma < 1e-3);
+            assert_eq!(hue, 0.0);
+        }
+    }
+
+    #[test]
+    fn clips_to_the_gamut_keeping_lightness_and_hue() {
+        // A green with more chroma than any screen shows
+        let clipped = oklch_to_rgb([70.0, 0.37, 150.0]);
+        let [lightness, chroma, hue] = rgb_to_oklch(clipped);
+        assert!((lightness - 70.0).abs() < 1.0, "{} lost its lightness", lightness);
+        assert!((hue - 150.0).abs() < 2.0, "{} lost its hue", hue);
+        assert!(chroma < 0.37);
+        // Nothing is left to clip but a channel at the edge of the gamut
+        assert!(clipped.iter().any(|channel| *channel == 0 || *channel == 255));
+
+        // Only white is that light
+        assert_eq!(oklch_to_rgb([100.0, 0.2, 30.0]), [255, 255, 255]);
+    }
+
+    #[test]
+    fn round_trips_through_hsv_and_hsl() {
+        for rgb in samples() {
+            assert_eq!(hsv_to_rgb(rgb_to_hsv(rgb)), rgb);
+            assert_eq!(hsl_to_rgb(rgb_to_hsl(rgb)), rgb);
+        }
+        assert_close(rgb_to_hsv([255, 128, 0]), [30.1, 100.0, 100.0], 0.1);
+        assert_close(rgb_to_hsl([0, 0, 255]), [240.0, 100.0, 50.0], 1e-4);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{path::Path, sync::atomic::{AtomicU8, Ordering}};
+
+use crate::{Config, KBDynamicEffect, KBLightMode, State, SharedState, apply_lighting, fade, update_dynamic};
+
+// Commands shared by everything that controls the keyboard from outside of the window.
+// They block while fading, so they shouldn't be called from the GUI or D-Bus threads directly.
+
+// The last brightness the backlight was on at, which turning it back on returns to
+static LIT_BRIGHTNESS: AtomicU8 = AtomicU8::new(100);
+
+/// Remembers `brightness` for turning the backlight back on, unless it's off
+pub fn remember_brightness(brightness: u8) {
+    if brightness > 0 {
+        LIT_BRIGHTNESS.store(brightness, Ordering::Relaxed);
+    }
+}
+
+pub fn lit_brightness() -> u8 {
+    LIT_BRIGHTNESS.load(Ordering::Relaxed)
+}
+
+/// The level of the 25% steps closest to `brightness`
+pub fn nearest_level(brightness: u8) -> u8 {
+    let step = fade::BRIGHTNESS_STEP;
+    (brightness.min(100) + step / 2) / step * step
+}
+
+/// The brightness closest to `brightness` the keyboard is set to, which moves in 25% steps unless it's fine-grained
+pub fn snap_brightness(cfg: &Config, brightness: u8) -> u8 {
+    if cfg.fade.fine_grained { brightness.min(100) } else { nearest_level(brightness) }
+}
+
+pub fn set_brightness(state: &SharedState, level: u8) {
+    let level = snap_brightness(&state.lock().unwrap().cfg, level);
+    remember_brightness(level);
+    fade::fade_brightness(state, level);
+}
+
+/// Steps the brightness up or down to the next 25% level, even when it's fine-grained
+pub fn step_brightness(state: &SharedState, up: bool) {
+    let brightness = nearest_level(state.lock().unwrap().cfg.kb.brightness) as i32;
+    let step = if up { fade::BRIGHTNESS_STEP as i32 } else { -(fade::BRIGHTNESS_STEP as i32) };
+
+    set_brightness(state, (brightness + step).clamp(0, 100) as u8);
+}
+
+pub fn toggle_backlight(state: &SharedState) {
+    let brightness = state.lock().unwrap().cfg.kb.brightness;
+    remember_brightness(brightness);
+    set_brightness(state, if brightness > 0 { 0 } else { lit_brightness() });
+}
+
+pub fn set_mode(state: &SharedState, mode: KBLightMode) {
+    let mut state = state.lock().unwrap();
+    let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+
+    cfg.kb.mode = mode;
+    apply_lighting(static_dev, dynamic_dev, cfg);
+}
+
+pub fn set_effect(state: &SharedState, effect: KBDynamicEffect) {
+    let mut state = state.lock().unwrap();
+    let State { cfg, dynamic_dev, .. } = &mut *state;
+
+    cfg.kb.mode = KBLightMode::Dynamic;
+    cfg.kb.effect = effect;
+    cfg.kb.speed = cfg.effects.capabilities(effect).clamp_speed(cfg.kb.speed);
+    update_dynamic(dynamic_dev, cfg);
+}
+
+/// Colors every zone in the static mode, or the effect in the dynamic mode. Scripts and plugins choose their own colors.
+/// Fails for effects without a color.
+pub fn set_color(state: &SharedState, color: [u8; 3]) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+    let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+
+    match cfg.kb.mode {
+        KBLightMode::Static => {
+            for zone in &mut cfg.kb.zones {
+                zone.color = color;
+            }
+        }
+        KBLightMode::Dynamic => {
+            cfg.effects.capabilities(cfg.kb.effect).check(cfg.kb.effect, None, false, true)?;
+            cfg.kb.color = color;
+        }
+        KBLightMode::Script | KBLightMode::Plugin => return Ok(())
+    }
+    apply_lighting(static_dev, dynamic_dev, cfg);
+
+    Ok(())
+}
+
+/// Switches to the effect after the current one, starting over after the last
+pub fn cycle_effect(state: &SharedState) {
+    let kb = state.lock().unwrap().cfg.kb;
+    let next = if kb.mode == KBLightMode::Dynamic {
+        let i = KBDynamicEffect::ALL.iter().position(|effect| *effect == kb.effect).unwrap_or(0);
+        KBDynamicEffect::ALL[(i + 1) % KBDynamicEffect::ALL.len()]
+    } else {
+        kb.effect
+    };
+
+    set_effect(state, next);
+}
+
+/// Returns false if there's no profile with that name
+pub fn apply_profile(state: &SharedState, name: &str) -> bool {
+    let kb = state.lock().unwrap().cfg.profile(name).map(|profile| profile.kb);
+
+    match kb {
+        Some(kb) => {
+            fade::transition(state, kb);
+            true
+        }
+        None => false
+    }
+}
+
+pub fn save(state: &SharedState, config_path: &Path) {
+    let _ = state.lock().unwrap().store(config_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snaps_to_the_nearest_step() {
+        assert_eq!([0, 12, 13, 37, 38, 99, 100, 255].map(nearest_level), [0, 0, 25, 25, 50, 100, 100, 100]);
+
+        let mut cfg = Config::default();
+        assert_eq!(snap_brightness(&cfg, 60), 50);
+        cfg.fade.fine_grained = true;
+        assert_eq!(snap_brightness(&cfg, 60), 60);
+        assert_eq!(snap_brightness(&cfg, 150), 100);
+    }
+}
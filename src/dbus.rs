@@ -0,0 +1,343 @@
+use std::{cell::Cell, path::PathBuf, sync::{Arc, Mutex}, thread, time::Duration};
+
+use zbus::{SignalContext, blocking::{Connection, ConnectionBuilder}, dbus_interface, fdo};
+
+use crate::{KBDynamicDirection, KBDynamicEffect, KBLightMode, KBLighting, State, SharedState, Zone, apply_lighting, change_brightness, control, toggle_zone, update_dynamic, write_to_static_dev};
+
+const BUS_NAME: &str = "org.predatorng.Keyboard";
+const OBJECT_PATH: &str = "/org/predatorng/Keyboard";
+
+// How often the lighting is compared with what was last announced, to catch changes made elsewhere
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+type Color = (u8, u8, u8);
+
+fn parse_mode(name: &str) -> fdo::Result<KBLightMode> {
+    KBLightMode::ALL.into_iter()
+        .find(|mode| mode.name() == name)
+        .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unknown mode {}", name)))
+}
+
+fn parse_effect(name: &str) -> fdo::Result<KBDynamicEffect> {
+    KBDynamicEffect::ALL.into_iter()
+        .find(|effect| effect.name() == name)
+        .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unknown effect {}", name)))
+}
+
+fn direction_name(direction: KBDynamicDirection) -> &'static str {
+    match direction {
+        KBDynamicDirection::None => "None",
+        KBDynamicDirection::LeftToRight => "LeftToRight",
+        KBDynamicDirection::RightToLeft => "RightToLeft"
+    }
+}
+
+fn parse_direction(name: &str) -> fdo::Result<KBDynamicDirection> {
+    [KBDynamicDirection::None, KBDynamicDirection::LeftToRight, KBDynamicDirection::RightToLeft].into_iter()
+        .find(|direction| direction_name(*direction) == name)
+        .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unknown direction {}", name)))
+}
+
+fn to_tuple(color: [u8; 3]) -> Color {
+    (color[0], color[1], color[2])
+}
+
+struct Keyboard {
+    state: SharedState,
+    config_path: PathBuf,
+    // The lighting as last announced, so the poller doesn't announce our own changes again
+    announced: Arc<Mutex<KBLighting>>
+}
+
+impl Keyboard {
+    fn kb(&self) -> KBLighting {
+        self.state.lock().unwrap().cfg.kb
+    }
+
+    /// Checks that the current effect takes the settings a client wants to give it
+    fn check(&self, speed: Option<u8>, direction: bool, color: bool) -> fdo::Result<()> {
+        let cfg = &self.state.lock().unwrap().cfg;
+        cfg.effects.capabilities(cfg.kb.effect).check(cfg.kb.effect, speed, direction, color).map_err(fdo::Error::InvalidArgs)
+    }
+
+    /// Changes the lighting, writes it with `write` and saves it
+    fn update(&self, change: impl FnOnce(&mut KBLighting), write: impl FnOnce(&mut State)) {
+        let mut state = self.state.lock().unwrap();
+        change(&mut state.cfg.kb);
+        write(&mut state);
+
+        *self.announced.lock().unwrap() = state.cfg.kb;
+        let _ = state.store(&self.config_path);
+    }
+
+    /// Rewrites the dynamic device, unless the static mode is shown
+    fn write_dynamic(state: &mut State) {
+        let State { cfg, dynamic_dev, .. } = state;
+        if cfg.kb.mode == KBLightMode::Dynamic {
+            update_dynamic(dynamic_dev, cfg);
+        }
+    }
+}
+
+// Setting the effect, speed, direction or color keeps the current mode, set `Mode` to show them
+#[dbus_interface(name = "org.predatorng.Keyboard")]
+impl Keyboard {
+    /// Fades to the saved profile with that name
+    fn apply_profile(&self, name: &str) -> fdo::Result<()> {
+        if self.state.lock().unwrap().cfg.profile(name).is_none() {
+            return Err(fdo::Error::InvalidArgs(format!("No profile named {}", name)));
+        }
+
+        let state = self.state.clone();
+        let config_path = self.config_path.clone();
+        let name = name.to_string();
+        thread::spawn(move || {
+            control::apply_profile(&state, &name);
+            control::save(&state, &config_path);
+        });
+
+        Ok(())
+    }
+
+    /// Sets the color of zone 1 to 3 and whether it's lit
+    async fn set_zone(&self, #[zbus(signal_context)] ctxt: SignalContext<'_>, zone: u32, color: Color, enabled: bool) -> fdo::Result<()> {
+        if !(1..=3).contains(&zone) {
+            return Err(fdo::Error::InvalidArgs(format!("There's no zone {}", zone)));
+        }
+        let zone = zone as usize;
+
+        // Read under the same lock as the change, so the zone is only toggled when this changed it
+        let was_enabled = Cell::new(enabled);
+        self.update(
+            |kb| {
+                was_enabled.set(kb.zones[zone - 1].enabled);
+                kb.zones[zone - 1] = Zone { color: [color.0, color.1, color.2], enabled };
+            },
+            |state| {
+                let State { cfg, static_dev, .. } = state;
+                if cfg.kb.mode == KBLightMode::Static {
+                    write_to_static_dev(static_dev, cfg, zone);
+                    if enabled != was_enabled.get() {
+                        toggle_zone(static_dev, cfg, zone);
+                    }
+                }
+            }
+        );
+        self.zones_changed(&ctxt).await?;
+
+        Ok(())
+    }
+
+    #[dbus_interface(property)]
+    fn mode(&self) -> &str {
+        self.kb().mode.name()
+    }
+
+    #[dbus_interface(property)]
+    fn set_mode(&mut self, name: &str) -> fdo::Result<()> {
+        let mode = parse_mode(name)?;
+        self.update(|kb| kb.mode = mode, |state| {
+            let State { cfg, static_dev, dynamic_dev, .. } = state;
+            apply_lighting(static_dev, dynamic_dev, cfg);
+        });
+
+        Ok(())
+    }
+
+    #[dbus_interface(property)]
+    fn brightness(&self) -> u8 {
+        self.kb().brightness
+    }
+
+    #[dbus_interface(property)]
+    fn set_brightness(&mut self, brightness: u8) -> fdo::Result<()> {
+        if brightness > 100 {
+            return Err(fdo::Error::InvalidArgs("The brightness goes from 0 to 100".to_string()));
+        }
+
+        let brightness = control::snap_brightness(&self.state.lock().unwrap().cfg, brightness);
+        self.update(|kb| kb.brightness = brightness, |state| {
+            let State { cfg, dynamic_dev, .. } = state;
+            change_brightness(dynamic_dev, cfg);
+        });
+
+        Ok(())
+    }
+
+    #[dbus_interface(property)]
+    fn effect(&self) -> &str {
+        self.kb().effect.name()
+    }
+
+    #[dbus_interface(property)]
+    fn set_effect(&mut self, name: &str) -> fdo::Result<()> {
+        let effect = parse_effect(name)?;
+        let capabilities = self.state.lock().unwrap().cfg.effects.capabilities(effect);
+        self.update(|kb| {
+            kb.effect = effect;
+            kb.speed = capabilities.clamp_speed(kb.speed);
+        }, Self::write_dynamic);
+
+        Ok(())
+    }
+
+    #[dbus_interface(property)]
+    fn speed(&self) -> u8 {
+        self.kb().speed
+    }
+
+    #[dbus_interface(property)]
+    fn set_speed(&mut self, speed: u8) -> fdo::Result<()> {
+        self.check(Some(speed), false, false)?;
+
+        self.update(|kb| kb.speed = speed, Self::write_dynamic);
+
+        Ok(())
+    }
+
+    #[dbus_interface(property)]
+    fn direction(&self) -> &str {
+        direction_name(self.kb().direction)
+    }
+
+    #[dbus_interface(property)]
+    fn set_direction(&mut self, name: &str) -> fdo::Result<()> {
+        let direction = parse_direction(name)?;
+        self.check(None, direction != KBDynamicDirection::None, false)?;
+        self.update(|kb| kb.direction = direction, Self::write_dynamic);
+
+        Ok(())
+    }
+
+    #[dbus_interface(property)]
+    fn color(&self) -> Color {
+        to_tuple(self.kb().color)
+    }
+
+    #[dbus_interface(property)]
+    fn set_color(&mut self, color: Color) -> fdo::Result<()> {
+        self.check(None, false, true)?;
+        self.update(|kb| kb.color = [color.0, color.1, color.2], Self::write_dynamic);
+
+        Ok(())
+    }
+
+    /// The color of each zone and whether it's lit
+    #[dbus_interface(property)]
+    fn zones(&self) -> Vec<(Color, bool)> {
+        self.kb().zones.iter().map(|zone| (to_tuple(zone.color), zone.enabled)).collect()
+    }
+}
+
+/// Announces the changes made by the window and the other background threads
+fn announce_changes(conn: &Connection, announced: &Mutex<KBLighting>, kb: KBLighting) -> zbus::Result<()> {
+    let last = std::mem::replace(&mut *announced.lock().unwrap(), kb);
+
+    let iface_ref = conn.object_server().interface::<_, Keyboard>(OBJECT_PATH)?;
+    let iface = iface_ref.get();
+    let ctxt = iface_ref.signal_context();
+
+    zbus::block_on(async {
+        if kb.mode != last.mode {
+            iface.mode_changed(ctxt).await?;
+        }
+        if kb.brightness != last.brightness {
+            iface.brightness_changed(ctxt).await?;
+        }
+        if kb.effect != last.effect {
+            iface.effect_changed(ctxt).await?;
+        }
+        if kb.speed != last.speed {
+            iface.speed_changed(ctxt).await?;
+        }
+        if kb.direction != last.direction {
+            iface.direction_changed(ctxt).await?;
+        }
+        if kb.color != last.color {
+            iface.color_changed(ctxt).await?;
+        }
+        if kb.zones != last.zones {
+            iface.zones_changed(ctxt).await?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Publishes the keyboard on the bus `builder` connects to, returning the connection and the lighting as last announced
+fn publish(builder: ConnectionBuilder<'_>, state: &SharedState, config_path: PathBuf) -> zbus::Result<(Connection, Arc<Mutex<KBLighting>>)> {
+    let announced = Arc::new(Mutex::new(state.lock().unwrap().cfg.kb));
+    let keyboard = Keyboard { state: state.clone(), config_path, announced: announced.clone() };
+
+    let conn = builder
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, keyboard)?
+        .build()?;
+
+    Ok((conn, announced))
+}
+
+fn serve(state: SharedState, config_path: PathBuf) -> zbus::Result<()> {
+    let (conn, announced) = publish(ConnectionBuilder::session()?, &state, config_path)?;
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let kb = state.lock().unwrap().cfg.kb;
+        if kb != *announced.lock().unwrap() {
+            announce_changes(&conn, &announced, kb)?;
+        }
+    }
+}
+
+/// Publishes the lighting on the session bus. `DBUS_SESSION_BUS_ADDRESS` can point this at a private bus.
+pub fn spawn(state: SharedState, config_path: PathBuf) {
+    thread::spawn(move || {
+        if let Err(e) = serve(state, config_path) {
+            eprintln!("[ERROR]: Could not publish the keyboard on D-Bus: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use zbus::blocking::Proxy;
+
+    use super::*;
+    use crate::testing::{PrivateBus, test_config_path, test_state};
+
+    #[test]
+    fn controls_the_keyboard_over_a_private_bus() {
+        let bus = PrivateBus::start();
+        let state = test_state("dbus");
+        let (_service, _) = publish(ConnectionBuilder::address(bus.address.as_str()).unwrap(), &state, test_config_path("dbus")).unwrap();
+
+        let conn = ConnectionBuilder::address(bus.address.as_str()).unwrap().build().unwrap();
+        let keyboard = Proxy::new(&conn, BUS_NAME, OBJECT_PATH, BUS_NAME).unwrap();
+
+        assert_eq!(keyboard.get_property::<String>("Mode").unwrap(), "Static");
+        keyboard.set_property("Mode", "Dynamic").unwrap();
+        assert!(state.lock().unwrap().cfg.kb.mode == KBLightMode::Dynamic);
+        assert!(keyboard.set_property("Mode", "Disco").is_err());
+
+        // Brightness moves in steps unless it's fine-grained
+        keyboard.set_property("Brightness", 60u8).unwrap();
+        assert_eq!(keyboard.get_property::<u8>("Brightness").unwrap(), 50);
+        assert!(keyboard.set_property("Brightness", 101u8).is_err());
+
+        keyboard.set_property("Effect", "Wave").unwrap();
+        keyboard.set_property("Speed", 3u8).unwrap();
+        keyboard.set_property("Direction", "RightToLeft").unwrap();
+        assert_eq!(keyboard.get_property::<String>("Effect").unwrap(), "Wave");
+        assert_eq!(keyboard.get_property::<u8>("Speed").unwrap(), 3);
+        assert_eq!(keyboard.get_property::<String>("Direction").unwrap(), "RightToLeft");
+        assert!(keyboard.set_property("Speed", 0u8).is_err());
+        // Wave chooses its own colors
+        assert!(keyboard.set_property("Color", (1u8, 2u8, 3u8)).is_err());
+
+        keyboard.call_method("SetZone", &(2u32, (10u8, 20u8, 30u8), false)).unwrap();
+        assert!(state.lock().unwrap().cfg.kb.zones[1] == Zone { color: [10, 20, 30], enabled: false });
+        assert!(keyboard.call_method("SetZone", &(4u32, (10u8, 20u8, 30u8), true)).is_err());
+        assert!(keyboard.call_method("ApplyProfile", &("Missing",)).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::{fs, sync::OnceLock};
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, KBDynamicEffect};
+
+const PRODUCT_NAME: &str = "/sys/class/dmi/id/product_name";
+const MIN_SPEED: u8 = 1;
+const MAX_SPEED: u8 = 9;
+
+/// What the firmware does with the settings of an effect
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    pub color: bool,
+    pub direction: bool,
+    pub min_speed: u8,
+    pub max_speed: u8
+}
+
+impl Capabilities {
+    /// How the effect behaves on most models
+    fn default_for(effect: KBDynamicEffect) -> Self {
+        let (color, direction) = match effect {
+            KBDynamicEffect::Breathing => (true, false),
+            KBDynamicEffect::Neon => (false, false),
+            KBDynamicEffect::Wave => (false, true),
+            KBDynamicEffect::Shifting => (false, true),
+            KBDynamicEffect::Zoom => (true, false),
+            KBDynamicEffect::Meteor => (true, true),
+            KBDynamicEffect::Twinkling => (true, false)
+        };
+
+        Self { color, direction, min_speed: MIN_SPEED, max_speed: MAX_SPEED }
+    }
+
+    pub fn speeds(&self) -> std::ops::RangeInclusive<u8> {
+        self.min_speed..=self.max_speed
+    }
+
+    /// The closest speed the effect takes, for when the effect changes
+    pub fn clamp_speed(&self, speed: u8) -> u8 {
+        speed.clamp(self.min_speed, self.max_speed)
+    }
+
+    /// Checks the settings a client wants to give the effect, naming the first one it doesn't take
+    pub fn check(&self, effect: KBDynamicEffect, speed: Option<u8>, direction: bool, color: bool) -> Result<(), String> {
+        if speed.is_some_and(|speed| !self.speeds().contains(&speed)) {
+            return Err(format!("The speed of {} goes from {} to {}", effect.name(), self.min_speed, self.max_speed));
+        }
+        if direction && !self.direction {
+            return Err(format!("{} has no direction", effect.name()));
+        }
+        if color && !self.color {
+            return Err(format!("{} has no color", effect.name()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Capabilities that differ on some models
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Override {
+    /// The model's name as in `/sys/class/dmi/id/product_name`, or the start of it to match a whole series
+    pub model: String,
+    pub effect: KBDynamicEffect,
+    pub capabilities: Capabilities
+}
+
+impl Override {
+    fn applies_to(&self, effect: KBDynamicEffect) -> bool {
+        self.effect == effect && model().starts_with(&self.model)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct EffectSettings {
+    pub overrides: Vec<Override>
+}
+
+/// The name of this laptop's model, empty if the firmware doesn't say
+pub fn model() -> &'static str {
+    static MODEL: OnceLock<String> = OnceLock::new();
+    MODEL.get_or_init(|| fs::read_to_string(PRODUCT_NAME).map(|name| name.trim().to_string()).unwrap_or_default())
+}
+
+impl EffectSettings {
+    fn find(&self, effect: KBDynamicEffect) -> Option<&Override> {
+        self.overrides.iter().find(|rule| rule.applies_to(effect))
+    }
+
+    /// What `effect` does on this model, the first matching override wins
+    pub fn capabilities(&self, effect: KBDynamicEffect) -> Capabilities {
+        self.find(effect).map_or_else(|| Capabilities::default_for(effect), |rule| rule.capabilities)
+    }
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config) -> bool {
+    let settings = &mut cfg.effects;
+    let mut changed = false;
+
+    ui.label(format!("Model: {}", if model().is_empty() { "Unknown" } else { model() }));
+    egui::Grid::new("EffectCapabilities").num_columns(5).show(ui, |ui| {
+        ui.label("Effect");
+        ui.label("Color");
+        ui.label("Direction");
+        ui.label("Speed");
+        // Over the reset buttons
+        ui.label("");
+        ui.end_row();
+
+        for effect in KBDynamicEffect::ALL {
+            let mut capabilities = settings.capabilities(effect);
+            let overridden = settings.find(effect).is_some();
+
+            ui.label(effect.name());
+            let mut edited = ui.checkbox(&mut capabilities.color, "").changed();
+            edited |= ui.checkbox(&mut capabilities.direction, "").changed();
+            ui.horizontal(|ui| {
+                edited |= ui.add(egui::DragValue::new(&mut capabilities.min_speed).clamp_range(MIN_SPEED..=capabilities.max_speed)).changed();
+                ui.label("to");
+                edited |= ui.add(egui::DragValue::new(&mut capabilities.max_speed).clamp_range(capabilities.min_speed..=MAX_SPEED)).changed();
+            });
+            let reset = ui.add_enabled(overridden, egui::Button::new("Reset"))
+                .on_hover_text("Go back to what the effect does on most models")
+                .clicked();
+            ui.end_row();
+
+            if edited {
+                // Whatever matched before keeps matching, new overrides are for this model only
+                match settings.overrides.iter_mut().find(|rule| rule.applies_to(effect)) {
+                    Some(rule) => rule.capabilities = capabilities,
+                    None => settings.overrides.push(Override { model: model().to_string(), effect, capabilities })
+                }
+                changed = true;
+            }
+            if reset {
+                settings.overrides.retain(|rule| !rule.applies_to(effect));
+                changed = true;
+            }
+        }
+    });
+
+    changed
+}
@@ -0,0 +1,145 @@
+use std::{thread, time::Duration};
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, KBLighting, State, SharedState, apply_lighting, change_brightness};
+
+// Most firmwares only accept these brightness levels
+pub const BRIGHTNESS_STEP: u8 = 25;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FadeSettings {
+    pub fine_grained: bool,
+    pub duration: u64
+}
+
+impl Default for FadeSettings {
+    fn default() -> Self {
+        Self {
+            fine_grained: false,
+            duration: 300
+        }
+    }
+}
+
+impl FadeSettings {
+    pub fn step(&self) -> u8 {
+        if self.fine_grained { 1 } else { BRIGHTNESS_STEP }
+    }
+}
+
+/// The brightness levels to go through when fading from `from` to `to`, excluding `from`
+fn levels(from: u8, to: u8, step: u8) -> Vec<u8> {
+    let mut levels: Vec<u8> = if from < to {
+        (from + 1..to).filter(|level| level % step == 0).collect()
+    } else {
+        (to + 1..from).rev().filter(|level| level % step == 0).collect()
+    };
+    levels.push(to);
+
+    levels
+}
+
+/// Goes through the levels from `from` to `to` over the configured fade duration, showing each one set with `set`.
+/// The state is only locked for each individual step so that other threads aren't blocked.
+fn fade(state: &SharedState, from: u8, to: u8, set: impl Fn(&mut Config, u8)) {
+    let fade = state.lock().unwrap().cfg.fade.clone();
+    let levels = levels(from, to, fade.step());
+    let interval = Duration::from_millis(fade.duration) / levels.len() as u32;
+
+    for level in levels {
+        {
+            let mut state = state.lock().unwrap();
+            let State { cfg, dynamic_dev, .. } = &mut *state;
+
+            set(cfg, level);
+            change_brightness(dynamic_dev, cfg);
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+/// Gradually changes the brightness to `to`
+pub fn fade_brightness(state: &SharedState, to: u8) {
+    let from = state.lock().unwrap().cfg.kb.brightness;
+    fade(state, from, to, |cfg, level| cfg.kb.brightness = level);
+}
+
+/// Gradually shows `to` on the keyboard without changing the brightness of the lighting, `None` fades back to it
+pub fn fade_overlay(state: &SharedState, to: Option<u8>) {
+    let (from, target) = {
+        let cfg = &state.lock().unwrap().cfg;
+        (cfg.brightness(), to.unwrap_or(cfg.kb.brightness))
+    };
+    fade(state, from, target, |cfg, level| cfg.overlay.brightness = Some(level));
+
+    let mut state = state.lock().unwrap();
+    let State { cfg, dynamic_dev, .. } = &mut *state;
+    cfg.overlay.brightness = to;
+    change_brightness(dynamic_dev, cfg);
+}
+
+/// Fades out, switches to the new lighting and fades back in to its brightness
+pub fn transition(state: &SharedState, kb: KBLighting) {
+    fade_brightness(state, 0);
+
+    {
+        let mut state = state.lock().unwrap();
+        let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+
+        cfg.kb = KBLighting { brightness: 0, ..kb };
+        apply_lighting(static_dev, dynamic_dev, cfg);
+    }
+
+    fade_brightness(state, kb.brightness);
+}
+
+pub fn spawn_fade_brightness(state: SharedState, to: u8) {
+    thread::spawn(move || fade_brightness(&state, to));
+}
+
+pub fn spawn_transition(state: SharedState, kb: KBLighting) {
+    thread::spawn(move || transition(&state, kb));
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config) -> bool {
+    let mut changed = false;
+
+    ui.horizontal(|ui| {
+        ui.label("Fade duration");
+        changed |= ui.add(egui::DragValue::new(&mut cfg.fade.duration).clamp_range(0..=5000).suffix(" ms")).changed();
+    });
+    changed |= ui.checkbox(&mut cfg.fade.fine_grained, "Fine-grained brightness")
+        .on_hover_text("Only enable this if your model's firmware accepts any brightness between 0 and 100")
+        .changed();
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_through_the_levels() {
+        assert_eq!(levels(0, 100, BRIGHTNESS_STEP), vec![25, 50, 75, 100]);
+        assert_eq!(levels(100, 0, BRIGHTNESS_STEP), vec![75, 50, 25, 0]);
+        // Ends in between the steps are kept
+        assert_eq!(levels(10, 60, BRIGHTNESS_STEP), vec![25, 50, 60]);
+        assert_eq!(levels(60, 10, BRIGHTNESS_STEP), vec![50, 25, 10]);
+        assert_eq!(levels(30, 40, BRIGHTNESS_STEP), vec![40]);
+        assert_eq!(levels(50, 50, BRIGHTNESS_STEP), vec![50]);
+    }
+
+    #[test]
+    fn goes_through_every_level_when_fine_grained() {
+        let step = FadeSettings { fine_grained: true, ..Default::default() }.step();
+        assert_eq!(levels(0, 5, step), vec![1, 2, 3, 4, 5]);
+        assert_eq!(levels(3, 0, step), vec![2, 1, 0]);
+        assert_eq!(levels(0, 100, step), (1..=100).collect::<Vec<u8>>());
+        assert_eq!(levels(100, 100, step), vec![100]);
+    }
+}
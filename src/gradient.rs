@@ -0,0 +1,214 @@
+use eframe::egui;
+use predator_ng::{color::{linear_to_rgb, oklab_to_rgb, oklch_to_rgb, rgb_to_linear, rgb_to_oklab, rgb_to_oklch}, widgets::color_picker::color_picker};
+use serde::{Deserialize, Serialize};
+
+const MAX_STOPS: usize = 8;
+// How many steps the preview bar is drawn with
+const PREVIEW_STEPS: usize = 48;
+
+const SCHEMES: [(&str, &[[u8; 3]]); 5] = [
+    ("Rainbow", &[[255, 0, 0], [255, 165, 0], [255, 255, 0], [0, 255, 0], [0, 0, 255], [148, 0, 211]]),
+    ("Sunset", &[[255, 94, 0], [255, 0, 110], [120, 0, 200]]),
+    ("Ocean", &[[0, 255, 200], [0, 120, 255], [20, 0, 160]]),
+    ("Fire", &[[255, 255, 0], [255, 100, 0], [255, 0, 0]]),
+    ("Forest", &[[180, 255, 0], [0, 200, 40], [0, 90, 60]])
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ColorSpace {
+    /// Mixes the channels as they are, which can turn muddy between opposite colors
+    Srgb,
+    /// Mixes the light, as two LEDs next to each other would
+    LinearRgb,
+    /// Mixes evenly to the eye
+    #[default] Oklab,
+    /// Goes around the hue circle, passing through the colors in between
+    Oklch
+}
+
+impl ColorSpace {
+    const ALL: [ColorSpace; 4] = [ColorSpace::Srgb, ColorSpace::LinearRgb, ColorSpace::Oklab, ColorSpace::Oklch];
+
+    fn name(self) -> &'static str {
+        match self {
+            ColorSpace::Srgb => "sRGB",
+            ColorSpace::LinearRgb => "Linear RGB",
+            ColorSpace::Oklab => "OKLab",
+            ColorSpace::Oklch => "OKLCH"
+        }
+    }
+
+    fn mix(self, from: [u8; 3], to: [u8; 3], t: f32) -> [u8; 3] {
+        let lerp = |from: [f32; 3], to: [f32; 3]| [0, 1, 2].map(|i| from[i] + (to[i] - from[i]) * t);
+
+        match self {
+            ColorSpace::Srgb => lerp(from.map(f32::from), to.map(f32::from)).map(|channel| channel.round() as u8),
+            ColorSpace::LinearRgb => linear_to_rgb(lerp(rgb_to_linear(from), rgb_to_linear(to))),
+            ColorSpace::Oklab => oklab_to_rgb(lerp(rgb_to_oklab(from), rgb_to_oklab(to))),
+            ColorSpace::Oklch => {
+                let (mut from, mut to) = (rgb_to_oklch(from), rgb_to_oklch(to));
+                // Greys have no hue of their own, so they take the other color's
+                if from[1] < 1e-3 { from[2] = to[2] }
+                if to[1] < 1e-3 { to[2] = from[2] }
+                // The short way around the hue circle
+                let turn = (to[2] - from[2] + 180.0).rem_euclid(360.0) - 180.0;
+                to[2] = from[2] + turn;
+                oklch_to_rgb(lerp(from, to))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct GradientSettings {
+    /// Spread evenly from the left to the right of the keyboard
+    pub stops: Vec<[u8; 3]>,
+    pub space: ColorSpace
+}
+
+impl Default for GradientSettings {
+    fn default() -> Self {
+        Self {
+            stops: SCHEMES[0].1.to_vec(),
+            space: Default::default()
+        }
+    }
+}
+
+impl GradientSettings {
+    /// The color at `t`, from 0 on the left to 1 on the right
+    fn sample(&self, t: f32) -> [u8; 3] {
+        match self.stops.as_slice() {
+            [] => [0; 3],
+            [only] => *only,
+            stops => {
+                let position = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+                let i = (position as usize).min(stops.len() - 2);
+                self.space.mix(stops[i], stops[i + 1], position - i as f32)
+            }
+        }
+    }
+
+    /// The colors of the zones, the outer zones get the first and last stop
+    pub fn zones(&self) -> [[u8; 3]; 3] {
+        [0.0, 0.5, 1.0].map(|t| self.sample(t))
+    }
+}
+
+fn preview(ui: &mut egui::Ui, settings: &GradientSettings) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width().min(300.0), 20.0), egui::Sense::hover());
+    let step_width = rect.width() / PREVIEW_STEPS as f32;
+
+    for step in 0..PREVIEW_STEPS {
+        let color = settings.sample(step as f32 / (PREVIEW_STEPS - 1) as f32);
+        let left = rect.left() + step as f32 * step_width;
+        let step_rect = egui::Rect::from_min_max(egui::pos2(left, rect.top()), egui::pos2(left + step_width + 0.5, rect.bottom()));
+        ui.painter().rect_filled(step_rect, 0.0, egui::Color32::from_rgb(color[0], color[1], color[2]));
+    }
+}
+
+/// Shows the stops and the color space, returning whether the settings changed and whether the zones should be filled
+pub fn show(ui: &mut egui::Ui, settings: &mut GradientSettings) -> (bool, bool) {
+    let mut changed = false;
+    let mut fill = false;
+
+    ui.horizontal(|ui| {
+        ui.label("Scheme");
+        egui::ComboBox::from_id_source("GradientScheme")
+            .selected_text(SCHEMES.iter().find(|(_, stops)| *stops == settings.stops.as_slice()).map_or("Custom", |(name, _)| name))
+            .show_ui(ui, |ui| {
+                for (name, stops) in SCHEMES {
+                    if ui.selectable_label(settings.stops == stops, name).clicked() {
+                        settings.stops = stops.to_vec();
+                        changed = true;
+                    }
+                }
+            });
+        ui.label("Mix in");
+        egui::ComboBox::from_id_source("GradientSpace")
+            .selected_text(settings.space.name())
+            .show_ui(ui, |ui| {
+                for space in ColorSpace::ALL {
+                    changed |= ui.selectable_value(&mut settings.space, space, space.name()).changed();
+                }
+            });
+    });
+
+    ui.horizontal_wrapped(|ui| {
+        ui.label("Stops");
+        let mut removed = None;
+        let removable = settings.stops.len() > 2;
+        for (i, stop) in settings.stops.iter_mut().enumerate() {
+            changed |= ui.add(color_picker(stop)).changed();
+            if removable && ui.small_button("x").on_hover_text("Remove this stop").clicked() {
+                removed = Some(i);
+            }
+        }
+        if let Some(i) = removed {
+            settings.stops.remove(i);
+            changed = true;
+        }
+        if ui.add_enabled(settings.stops.len() < MAX_STOPS, egui::Button::new("+")).on_hover_text("Add a stop").clicked() {
+            let last = settings.stops.last().copied().unwrap_or([255; 3]);
+            settings.stops.push(last);
+            changed = true;
+        }
+    });
+
+    ui.horizontal(|ui| {
+        preview(ui, settings);
+        fill = ui.button("Fill").on_hover_text("Give the zones these colors from left to right").clicked();
+    });
+
+    (changed, fill)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hue(rgb: [u8; 3]) -> f32 {
+        rgb_to_oklch(rgb)[2]
+    }
+
+    // How far apart two hues are around the circle
+    fn hue_distance(from: f32, to: f32) -> f32 {
+        let distance = (to - from).rem_euclid(360.0);
+        distance.min(360.0 - distance)
+    }
+
+    #[test]
+    fn oklch_takes_the_short_way_around_the_hue_circle() {
+        let from = oklch_to_rgb([60.0, 0.12, 350.0]);
+        let to = oklch_to_rgb([60.0, 0.12, 10.0]);
+
+        // Through red at 0°, not through green at 180°
+        let middle = ColorSpace::Oklch.mix(from, to, 0.5);
+        assert!(hue_distance(hue(middle), 0.0) < 3.0, "{} went the long way", hue(middle));
+        let middle = ColorSpace::Oklch.mix(to, from, 0.5);
+        assert!(hue_distance(hue(middle), 0.0) < 3.0, "{} went the long way", hue(middle));
+
+        let gradient = GradientSettings { stops: vec![from, to], space: ColorSpace::Oklch };
+        for step in 0..=10 {
+            let sampled = hue(gradient.sample(step as f32 / 10.0));
+            assert!(hue_distance(sampled, 0.0) <= 12.0, "{} at step {} is outside of 350° to 10°", sampled, step);
+        }
+        assert_eq!(gradient.zones(), [from, gradient.sample(0.5), to]);
+    }
+
+    #[test]
+    fn greys_take_the_other_colors_hue() {
+        let red = [255, 0, 0];
+        let middle = ColorSpace::Oklch.mix([128; 3], red, 0.5);
+        assert!(hue_distance(hue(middle), hue(red)) < 3.0);
+    }
+
+    #[test]
+    fn samples_the_stops() {
+        let gradient = GradientSettings { stops: vec![[255, 0, 0], [0, 255, 0], [0, 0, 255]], space: ColorSpace::Srgb };
+        assert_eq!(gradient.zones(), [[255, 0, 0], [0, 255, 0], [0, 0, 255]]);
+        assert_eq!(gradient.sample(0.25), [128, 128, 0]);
+        assert_eq!(GradientSettings { stops: Vec::new(), space: ColorSpace::Oklab }.sample(0.5), [0; 3]);
+    }
+}
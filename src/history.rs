@@ -0,0 +1,200 @@
+use std::fs::File;
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, KBLighting, apply_lighting};
+
+// Older steps are forgotten
+const MAX_STEPS: usize = 100;
+const UNDO: egui::KeyboardShortcut = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+const REDO: egui::KeyboardShortcut = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND.plus(egui::Modifiers::SHIFT), egui::Key::Z);
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct HistorySettings {
+    /// Changes are only shown on the keyboard until they're applied
+    pub preview: bool
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<KBLighting>,
+    redo: Vec<KBLighting>,
+    // The pointer press the last step was made during, a whole drag is a single step
+    press: Option<f64>
+}
+
+impl History {
+    /// Makes a step back to `before` if the window changed the lighting to `after`
+    pub fn record(&mut self, ctx: &egui::Context, before: KBLighting, after: KBLighting) {
+        let press = ctx.input(|input| input.pointer.any_down().then(|| input.pointer.press_start_time()).flatten());
+
+        if before != after {
+            if press.is_none() || press != self.press {
+                self.undo.push(before);
+                if self.undo.len() > MAX_STEPS {
+                    self.undo.remove(0);
+                }
+            }
+            self.redo.clear();
+        }
+        self.press = press;
+    }
+
+    fn push_undo(&mut self, kb: KBLighting) {
+        self.undo.push(kb);
+        self.redo.clear();
+    }
+}
+
+// Moves the lighting to the last step of `from`, keeping the current one in `to`
+fn step(from: &mut Vec<KBLighting>, to: &mut Vec<KBLighting>, kb: &mut KBLighting) -> bool {
+    match from.pop() {
+        Some(previous) => {
+            to.push(std::mem::replace(kb, previous));
+            true
+        }
+        None => false
+    }
+}
+
+/// Shows Undo and Redo, the preview toggle and, while previewing, Apply and Revert.
+/// `saved` is the lighting kept in the config file while changes are previewed.
+/// Returns whether the config should be stored.
+pub fn show(ui: &mut egui::Ui, history: &mut History, static_dev: &mut File, dynamic_dev: &mut File, cfg: &mut Config, saved: &mut Option<KBLighting>) -> bool {
+    let mut changed = false;
+
+    if cfg.history.preview && saved.is_none() {
+        *saved = Some(cfg.kb);
+    }
+
+    // Text fields have an undo of their own
+    let (mut undo, mut redo) = if ui.ctx().wants_keyboard_input() {
+        (false, false)
+    } else {
+        ui.input_mut(|input| (input.consume_shortcut(&UNDO), input.consume_shortcut(&REDO)))
+    };
+
+    ui.horizontal(|ui| {
+        let undo_hint = ui.ctx().format_shortcut(&UNDO);
+        let redo_hint = ui.ctx().format_shortcut(&REDO);
+        undo |= ui.add_enabled(!history.undo.is_empty(), egui::Button::new("Undo")).on_hover_text(undo_hint).clicked();
+        redo |= ui.add_enabled(!history.redo.is_empty(), egui::Button::new("Redo")).on_hover_text(redo_hint).clicked();
+
+        if ui.checkbox(&mut cfg.history.preview, "Preview changes")
+            .on_hover_text("Only show changes on the keyboard until they're applied, turning this off keeps them")
+            .changed() {
+            *saved = cfg.history.preview.then_some(cfg.kb);
+            changed = true;
+        }
+
+        if let Some(kb) = saved.filter(|kb| *kb != cfg.kb) {
+            if ui.button("Apply").on_hover_text("Save the lighting shown on the keyboard").clicked() {
+                *saved = Some(cfg.kb);
+                changed = true;
+            }
+            if ui.button("Revert").on_hover_text("Go back to the saved lighting").clicked() {
+                history.push_undo(cfg.kb);
+                cfg.kb = kb;
+                apply_lighting(static_dev, dynamic_dev, cfg);
+            }
+        }
+    });
+
+    if (undo && step(&mut history.undo, &mut history.redo, &mut cfg.kb)) || (redo && step(&mut history.redo, &mut history.undo, &mut cfg.kb)) {
+        apply_lighting(static_dev, dynamic_dev, cfg);
+        changed = true;
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lighting(brightness: u8) -> KBLighting {
+        KBLighting { brightness, ..Default::default() }
+    }
+
+    fn brightnesses(steps: &[KBLighting]) -> Vec<u8> {
+        steps.iter().map(|kb| kb.brightness).collect()
+    }
+
+    // Records a change in a frame where the pointer is pressed if `pressed`
+    fn record(ctx: &egui::Context, history: &mut History, pressed: bool, before: u8, after: u8) {
+        let mut input = egui::RawInput::default();
+        if pressed {
+            let pos = egui::pos2(10.0, 10.0);
+            input.events.push(egui::Event::PointerMoved(pos));
+            input.events.push(egui::Event::PointerButton { pos, button: egui::PointerButton::Primary, pressed: true, modifiers: Default::default() });
+        }
+        let _ = ctx.run(input, |ctx| history.record(ctx, lighting(before), lighting(after)));
+    }
+
+    #[test]
+    fn undoes_and_redoes_in_order() {
+        let ctx = egui::Context::default();
+        let mut history = History::default();
+        record(&ctx, &mut history, false, 25, 50);
+        record(&ctx, &mut history, false, 50, 75);
+        // Nothing changed, so there's nothing to undo
+        record(&ctx, &mut history, false, 75, 75);
+        assert_eq!(brightnesses(&history.undo), vec![25, 50]);
+
+        let mut kb = lighting(75);
+        assert!(step(&mut history.undo, &mut history.redo, &mut kb));
+        assert_eq!(kb.brightness, 50);
+        assert!(step(&mut history.undo, &mut history.redo, &mut kb));
+        assert_eq!(kb.brightness, 25);
+        assert!(!step(&mut history.undo, &mut history.redo, &mut kb));
+        assert_eq!(brightnesses(&history.redo), vec![75, 50]);
+
+        assert!(step(&mut history.redo, &mut history.undo, &mut kb));
+        assert_eq!(kb.brightness, 50);
+        assert_eq!((brightnesses(&history.undo), brightnesses(&history.redo)), (vec![25], vec![75]));
+    }
+
+    #[test]
+    fn new_changes_clear_redo() {
+        let ctx = egui::Context::default();
+        let mut history = History::default();
+        record(&ctx, &mut history, false, 25, 50);
+
+        let mut kb = lighting(50);
+        step(&mut history.undo, &mut history.redo, &mut kb);
+        assert_eq!(history.redo.len(), 1);
+
+        record(&ctx, &mut history, false, 25, 100);
+        assert!(history.redo.is_empty());
+        assert_eq!(brightnesses(&history.undo), vec![25]);
+
+        history.push_undo(lighting(100));
+        assert_eq!(brightnesses(&history.undo), vec![25, 100]);
+    }
+
+    #[test]
+    fn forgets_the_oldest_steps() {
+        let ctx = egui::Context::default();
+        let mut history = History::default();
+        for brightness in 0..=MAX_STEPS as u8 + 10 {
+            record(&ctx, &mut history, false, brightness, brightness + 1);
+        }
+
+        assert_eq!(history.undo.len(), MAX_STEPS);
+        assert_eq!(history.undo[0].brightness, 11);
+        assert_eq!(history.undo[MAX_STEPS - 1].brightness, MAX_STEPS as u8 + 10);
+    }
+
+    #[test]
+    fn a_drag_is_one_step() {
+        let ctx = egui::Context::default();
+        let mut history = History::default();
+        record(&ctx, &mut history, true, 0, 10);
+        // The pointer is still held down in the frames after it was pressed
+        record(&ctx, &mut history, false, 10, 20);
+        record(&ctx, &mut history, false, 20, 30);
+        assert_eq!(brightnesses(&history.undo), vec![0]);
+    }
+}
@@ -0,0 +1,209 @@
+use std::{path::{Path, PathBuf}, sync::Mutex, thread};
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, SharedState, control, input};
+
+// Key codes from linux/input-event-codes.h
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_RIGHTSHIFT: u16 = 54;
+const KEY_LEFTALT: u16 = 56;
+const KEY_RIGHTCTRL: u16 = 97;
+const KEY_RIGHTALT: u16 = 100;
+const KEY_LEFTMETA: u16 = 125;
+const KEY_RIGHTMETA: u16 = 126;
+
+const KEYS: [(&str, u16); 15] = [
+    ("F1", 59),
+    ("F2", 60),
+    ("F3", 61),
+    ("F4", 62),
+    ("F5", 63),
+    ("F6", 64),
+    ("F7", 65),
+    ("F8", 66),
+    ("F9", 67),
+    ("F10", 68),
+    ("F11", 87),
+    ("F12", 88),
+    // Sent by the Fn key combinations on most models
+    ("KbdIllumToggle", 228),
+    ("KbdIllumDown", 229),
+    ("KbdIllumUp", 230)
+];
+
+const KEY_PRESSED: i32 = 1;
+const KEY_RELEASED: i32 = 0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum HotkeyAction {
+    CycleEffect,
+    BrightnessUp,
+    BrightnessDown,
+    ToggleBacklight
+}
+
+impl HotkeyAction {
+    const ALL: [HotkeyAction; 4] = [
+        HotkeyAction::CycleEffect,
+        HotkeyAction::BrightnessUp,
+        HotkeyAction::BrightnessDown,
+        HotkeyAction::ToggleBacklight
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            HotkeyAction::CycleEffect => "Cycle effect",
+            HotkeyAction::BrightnessUp => "Brightness up",
+            HotkeyAction::BrightnessDown => "Brightness down",
+            HotkeyAction::ToggleBacklight => "Toggle backlight"
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct Modifiers {
+    pub super_key: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Hotkey {
+    pub modifiers: Modifiers,
+    pub key: String,
+    pub action: HotkeyAction
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct HotkeySettings {
+    pub enabled: bool,
+    pub hotkeys: Vec<Hotkey>
+}
+
+impl Default for HotkeySettings {
+    fn default() -> Self {
+        let with_super = Modifiers { super_key: true, ..Default::default() };
+
+        Self {
+            enabled: false,
+            hotkeys: vec![
+                Hotkey { modifiers: with_super, key: "F9".to_string(), action: HotkeyAction::CycleEffect },
+                Hotkey { modifiers: with_super, key: "F10".to_string(), action: HotkeyAction::BrightnessDown },
+                Hotkey { modifiers: with_super, key: "F11".to_string(), action: HotkeyAction::BrightnessUp },
+                Hotkey { modifiers: Default::default(), key: "KbdIllumToggle".to_string(), action: HotkeyAction::ToggleBacklight }
+            ]
+        }
+    }
+}
+
+fn key_code(name: &str) -> Option<u16> {
+    KEYS.iter().find(|(key, _)| *key == name).map(|(_, code)| *code)
+}
+
+fn update_modifiers(modifiers: &mut Modifiers, code: u16, pressed: bool) -> bool {
+    match code {
+        KEY_LEFTMETA | KEY_RIGHTMETA => modifiers.super_key = pressed,
+        KEY_LEFTCTRL | KEY_RIGHTCTRL => modifiers.ctrl = pressed,
+        KEY_LEFTALT | KEY_RIGHTALT => modifiers.alt = pressed,
+        KEY_LEFTSHIFT | KEY_RIGHTSHIFT => modifiers.shift = pressed,
+        _ => return false
+    }
+
+    true
+}
+
+fn perform(state: &SharedState, config_path: &Path, action: HotkeyAction) {
+    match action {
+        HotkeyAction::CycleEffect => control::cycle_effect(state),
+        HotkeyAction::BrightnessUp => control::step_brightness(state, true),
+        HotkeyAction::BrightnessDown => control::step_brightness(state, false),
+        HotkeyAction::ToggleBacklight => control::toggle_backlight(state)
+    }
+
+    control::save(state, config_path);
+}
+
+/// Returns the input handler that runs the actions bound to hotkeys
+pub fn handler(state: SharedState, config_path: PathBuf) -> input::Handler {
+    let modifiers = Mutex::new(Modifiers::default());
+
+    Box::new(move |event| {
+        if event.kind != input::EV_KEY {
+            return;
+        }
+
+        let mut modifiers = modifiers.lock().unwrap();
+        if update_modifiers(&mut modifiers, event.code, event.value != KEY_RELEASED) || event.value != KEY_PRESSED {
+            return;
+        }
+
+        let settings = state.lock().unwrap().cfg.hotkeys.clone();
+        if !settings.enabled {
+            return;
+        }
+
+        let bound = settings.hotkeys.iter()
+            .find(|hotkey| hotkey.modifiers == *modifiers && key_code(&hotkey.key) == Some(event.code));
+        if let Some(hotkey) = bound {
+            let state = state.clone();
+            let config_path = config_path.clone();
+            let action = hotkey.action;
+            thread::spawn(move || perform(&state, &config_path, action));
+        }
+    })
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config) -> bool {
+    let settings = &mut cfg.hotkeys;
+
+    let mut changed = ui.checkbox(&mut settings.enabled, "Enable global hotkeys")
+        .on_hover_text("Requires access to the input devices, usually by being in the input group")
+        .changed();
+    ui.add_enabled_ui(settings.enabled, |ui| {
+        let mut removed = None;
+        egui::Grid::new("Hotkeys").num_columns(4).show(ui, |ui| {
+            for (i, hotkey) in settings.hotkeys.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    changed |= ui.toggle_value(&mut hotkey.modifiers.super_key, "Super").changed();
+                    changed |= ui.toggle_value(&mut hotkey.modifiers.ctrl, "Ctrl").changed();
+                    changed |= ui.toggle_value(&mut hotkey.modifiers.alt, "Alt").changed();
+                    changed |= ui.toggle_value(&mut hotkey.modifiers.shift, "Shift").changed();
+                });
+                egui::ComboBox::from_id_source(format!("HotkeyKey{}", i))
+                    .selected_text(hotkey.key.as_str())
+                    .show_ui(ui, |ui| {
+                        for (key, _) in KEYS {
+                            changed |= ui.selectable_value(&mut hotkey.key, key.to_string(), key).changed();
+                        }
+                    });
+                egui::ComboBox::from_id_source(format!("HotkeyAction{}", i))
+                    .selected_text(hotkey.action.name())
+                    .show_ui(ui, |ui| {
+                        for action in HotkeyAction::ALL {
+                            changed |= ui.selectable_value(&mut hotkey.action, action, action.name()).changed();
+                        }
+                    });
+                if ui.button("Remove").clicked() {
+                    removed = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = removed {
+            settings.hotkeys.remove(i);
+            changed = true;
+        }
+
+        if ui.button("Add hotkey").clicked() {
+            settings.hotkeys.push(Hotkey { modifiers: Default::default(), key: "F12".to_string(), action: HotkeyAction::ToggleBacklight });
+            changed = true;
+        }
+    });
+
+    changed
+}
@@ -0,0 +1,88 @@
+use std::{sync::mpsc::{self, RecvTimeoutError}, thread, time::Duration};
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, SharedState, fade, input};
+
+// How often to check whether the timeout has been enabled while it's off
+const DISABLED_RECHECK: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct IdleSettings {
+    pub enabled: bool,
+    pub timeout: u64
+}
+
+impl Default for IdleSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout: 60
+        }
+    }
+}
+
+// The keyboard is only dimmed on top of the lighting, so nothing that's saved or published changes
+fn dim(state: &SharedState) {
+    fade::fade_overlay(state, Some(0));
+}
+
+fn restore(state: &SharedState) {
+    fade::fade_overlay(state, None);
+}
+
+/// Starts the idle timer and returns the input handler that resets it
+pub fn spawn(state: SharedState) -> input::Handler {
+    let (activity_tx, activity_rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut dimmed = false;
+
+        loop {
+            let idle = state.lock().unwrap().cfg.idle.clone();
+
+            if !idle.enabled && dimmed {
+                restore(&state);
+                dimmed = false;
+            }
+
+            let wait = if idle.enabled { Duration::from_secs(idle.timeout) } else { DISABLED_RECHECK };
+            match activity_rx.recv_timeout(wait) {
+                Ok(()) => {
+                    if dimmed {
+                        restore(&state);
+                        dimmed = false;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if idle.enabled && !dimmed {
+                        dim(&state);
+                        dimmed = true;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return
+            }
+        }
+    });
+
+    Box::new(move |event| {
+        // Keys, buttons and pointer movement, but not the LEDs or switches that change by themselves
+        if event.kind == input::EV_KEY || event.kind == input::EV_REL {
+            let _ = activity_tx.send(());
+        }
+    })
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config) -> bool {
+    let mut changed = ui.checkbox(&mut cfg.idle.enabled, "Turn off the keyboard backlight when idle").changed();
+    ui.add_enabled_ui(cfg.idle.enabled, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Timeout");
+            changed |= ui.add(egui::DragValue::new(&mut cfg.idle.timeout).clamp_range(5..=3600).suffix(" s")).changed();
+        });
+    });
+
+    changed
+}
@@ -0,0 +1,97 @@
+use std::{collections::HashSet, fs::{self, File}, io::Read, mem::size_of, path::PathBuf, sync::{Arc, Mutex}, thread, time::Duration};
+
+const INPUT_DIR: &str = "/dev/input";
+const RESCAN_INTERVAL: Duration = Duration::from_secs(10);
+
+// struct input_event is a timeval followed by a u16 type, u16 code and i32 value
+const TIMEVAL_SIZE: usize = 2 * size_of::<usize>();
+const EVENT_SIZE: usize = TIMEVAL_SIZE + 8;
+
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+
+#[derive(Debug, Clone, Copy)]
+pub struct InputEvent {
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32
+}
+
+pub type Handler = Box<dyn Fn(&InputEvent) + Send + Sync>;
+
+fn parse_event(buf: &[u8; EVENT_SIZE]) -> InputEvent {
+    let data = &buf[TIMEVAL_SIZE..];
+
+    InputEvent {
+        kind: u16::from_ne_bytes([data[0], data[1]]),
+        code: u16::from_ne_bytes([data[2], data[3]]),
+        value: i32::from_ne_bytes([data[4], data[5], data[6], data[7]])
+    }
+}
+
+fn read_device(mut dev: File, handlers: &[Handler], wanted: &(dyn Fn() -> bool + Send + Sync)) {
+    let mut buf = [0; EVENT_SIZE];
+
+    // The device is closed with the first event after nothing wants input anymore
+    while dev.read_exact(&mut buf).is_ok() && wanted() {
+        let event = parse_event(&buf);
+        for handler in handlers {
+            handler(&event);
+        }
+    }
+}
+
+fn event_devices() -> Vec<PathBuf> {
+    fs::read_dir(INPUT_DIR)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.file_name().is_some_and(|name| name.to_string_lossy().starts_with("event")))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Reads events from every `/dev/input/event*` device and passes them to all of the handlers,
+/// for as long as `wanted` says they're needed. Reading input devices usually requires being in the `input` group.
+pub fn spawn(handlers: Vec<Handler>, wanted: impl Fn() -> bool + Send + Sync + 'static) {
+    let handlers = Arc::new(handlers);
+    let wanted = Arc::new(wanted);
+    let opened: Arc<Mutex<HashSet<PathBuf>>> = Default::default();
+
+    thread::spawn(move || {
+        let mut reported = false;
+
+        loop {
+            let devices = if wanted() { event_devices() } else { Vec::new() };
+            for path in devices {
+                if opened.lock().unwrap().contains(&path) {
+                    continue;
+                }
+
+                match File::open(&path) {
+                    Ok(dev) => {
+                        opened.lock().unwrap().insert(path.clone());
+
+                        let handlers = handlers.clone();
+                        let wanted = wanted.clone();
+                        let opened = opened.clone();
+                        thread::spawn(move || {
+                            read_device(dev, &handlers, &*wanted);
+                            // The device was most likely unplugged
+                            opened.lock().unwrap().remove(&path);
+                        });
+                    }
+                    Err(e) if !reported => {
+                        eprintln!("[ERROR]: Could not open input device {}: {}", path.display(), e);
+                        reported = true;
+                    }
+                    Err(_) => {}
+                }
+            }
+
+            thread::sleep(RESCAN_INTERVAL);
+        }
+    });
+}
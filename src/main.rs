@@ -1,16 +1,56 @@
-use std::{path::{PathBuf, Path}, env::var, fs::{OpenOptions, File}, io::Write};
+use std::{path::{PathBuf, Path}, env::var, fs::{OpenOptions, File}, io::Write, sync::{Arc, Condvar, Mutex}};
 
 use eframe::egui;
-use egui::TextureHandle;
 use serde::{Deserialize, Serialize};
-use egui_extras::image;
 
-use predator_ng::widgets::{toggle::*, color_box::*};
+use predator_ng::widgets::{toggle::*, color_picker::*, keyboard::{self, keyboard}, zone_editor::*};
+
+mod accent;
+mod action;
+mod api;
+mod apps;
+mod calibration;
+mod control;
+mod dbus;
+mod effects;
+mod fade;
+mod gradient;
+mod history;
+mod hotkeys;
+mod idle;
+mod input;
+mod mqtt;
+mod notify;
+mod openrgb;
+mod palette;
+mod plugins;
+mod power;
+mod schedule;
+mod script;
+#[cfg(test)]
+mod testing;
+mod tray;
+mod wallpaper;
 
 #[derive(PartialEq, Deserialize, Serialize, Default, Copy, Clone)]
 enum KBLightMode {
     #[default] Static,
-    Dynamic
+    Dynamic,
+    Script,
+    Plugin
+}
+
+impl KBLightMode {
+    const ALL: [KBLightMode; 4] = [KBLightMode::Static, KBLightMode::Dynamic, KBLightMode::Script, KBLightMode::Plugin];
+
+    fn name(self) -> &'static str {
+        match self {
+            KBLightMode::Static => "Static",
+            KBLightMode::Dynamic => "Dynamic",
+            KBLightMode::Script => "Script",
+            KBLightMode::Plugin => "Plugin"
+        }
+    }
 }
 
 #[derive(PartialEq, Deserialize, Serialize, Default, Copy, Clone)]
@@ -24,6 +64,30 @@ enum KBDynamicEffect {
     Twinkling
 }
 
+impl KBDynamicEffect {
+    const ALL: [KBDynamicEffect; 7] = [
+        KBDynamicEffect::Breathing,
+        KBDynamicEffect::Neon,
+        KBDynamicEffect::Wave,
+        KBDynamicEffect::Shifting,
+        KBDynamicEffect::Zoom,
+        KBDynamicEffect::Meteor,
+        KBDynamicEffect::Twinkling
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            KBDynamicEffect::Breathing => "Breathing",
+            KBDynamicEffect::Neon => "Neon",
+            KBDynamicEffect::Wave => "Wave",
+            KBDynamicEffect::Shifting => "Shifting",
+            KBDynamicEffect::Zoom => "Zoom",
+            KBDynamicEffect::Meteor => "Meteor",
+            KBDynamicEffect::Twinkling => "Twinkling"
+        }
+    }
+}
+
 #[derive(PartialEq, Deserialize, Serialize, Copy, Clone, Default)]
 enum KBDynamicDirection {
     None,
@@ -31,27 +95,13 @@ enum KBDynamicDirection {
     RightToLeft
 }
 
-#[non_exhaustive]
-struct PresetDynamicColor;
-
-impl PresetDynamicColor {
-    pub const RED: [u8; 3] = [255, 0, 0];
-    pub const ORANGE: [u8; 3] = [255, 165, 0];
-    pub const YELLOW: [u8; 3] = [255, 255, 0];
-    pub const GREEN: [u8; 3] = [0, 128, 0];
-    pub const BLUE: [u8; 3] = [0, 0, 255];
-    pub const INDIGO: [u8; 3] = [75, 0, 130];
-    pub const VIOLET: [u8; 3] = [148, 0, 211];
-    pub const WHITE: [u8; 3] = [255, 255, 255];
-}
-
-#[derive(Serialize, Deserialize, Copy, Clone)]
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
 struct Zone {
     color: [u8; 3],
     enabled: bool
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone)]
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
 struct KBLighting {
     mode: KBLightMode,
     brightness: u8,
@@ -70,7 +120,7 @@ impl Default for KBLighting {
             direction: Default::default(),
             effect: Default::default(),
             speed: 5,
-            color: PresetDynamicColor::WHITE,
+            color: [255, 255, 255],
             zones: [
                 Zone {
                     color: [255, 255, 255],
@@ -89,55 +139,222 @@ impl Default for KBLighting {
     }
 }
 
-#[derive(Default, Serialize, Deserialize, Copy, Clone)]
+impl KBLighting {
+    /// What the preview shows, `None` for the modes that draw on the keyboard themselves.
+    /// `capabilities` are those of the effect, for how fast its speed is.
+    fn preview(&self, capabilities: effects::Capabilities) -> Option<keyboard::Lighting> {
+        let effect = match self.effect {
+            KBDynamicEffect::Breathing => keyboard::Effect::Breathing,
+            KBDynamicEffect::Neon => keyboard::Effect::Neon,
+            KBDynamicEffect::Wave => keyboard::Effect::Wave,
+            KBDynamicEffect::Shifting => keyboard::Effect::Shifting,
+            KBDynamicEffect::Zoom => keyboard::Effect::Zoom,
+            KBDynamicEffect::Meteor => keyboard::Effect::Meteor,
+            KBDynamicEffect::Twinkling => keyboard::Effect::Twinkling
+        };
+        let direction = match self.direction {
+            KBDynamicDirection::RightToLeft => keyboard::Direction::RightToLeft,
+            _ => keyboard::Direction::LeftToRight
+        };
+
+        match self.mode {
+            KBLightMode::Static => Some(keyboard::Lighting::Static(self.zones.map(|zone| zone.enabled.then_some(zone.color)))),
+            KBLightMode::Dynamic => Some(keyboard::Lighting::Dynamic {
+                effect,
+                speed: self.speed,
+                min_speed: capabilities.min_speed,
+                max_speed: capabilities.max_speed,
+                direction,
+                color: self.color
+            }),
+            KBLightMode::Script | KBLightMode::Plugin => None
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Profile {
+    name: String,
+    kb: KBLighting
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
 struct Config {
     kb: KBLighting,
+    profiles: Vec<Profile>,
+    history: history::HistorySettings,
+    effects: effects::EffectSettings,
+    palette: palette::PaletteSettings,
+    gradient: gradient::GradientSettings,
+    power: power::PowerRules,
+    idle: idle::IdleSettings,
+    fade: fade::FadeSettings,
+    calibration: calibration::CalibrationSettings,
+    schedule: schedule::Schedule,
+    apps: apps::AppRules,
+    accent: accent::AccentSettings,
+    wallpaper: wallpaper::WallpaperSettings,
+    notifications: notify::NotificationSettings,
+    tray: tray::TraySettings,
+    hotkeys: hotkeys::HotkeySettings,
+    openrgb: openrgb::OpenRgbSettings,
+    mqtt: mqtt::MqttSettings,
+    api: api::ApiSettings,
+    script: script::ScriptSettings,
+    plugins: plugins::PluginSettings,
+    #[serde(skip)]
+    overlay: Overlay
+}
+
+/// What the keyboard shows instead of parts of the lighting, without changing the lighting or being stored
+#[derive(Default, Clone, Copy)]
+struct Overlay {
+    /// Replaces the brightness, to dim the keyboard while idle
+    brightness: Option<u8>,
+    /// Replaces the color of each zone and lights it, to mirror a device from OpenRGB
+    zones: [Option<[u8; 3]>; 3]
 }
 
-fn update_dynamic(dynamic_dev: &mut File, cfg: Config) {
+impl Config {
+    fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    /// The brightness the keyboard shows
+    fn brightness(&self) -> u8 {
+        self.overlay.brightness.unwrap_or(self.kb.brightness)
+    }
+
+    /// Whether zones are mirrored, which shows them whatever the mode
+    fn mirroring(&self) -> bool {
+        self.overlay.zones.iter().any(Option::is_some)
+    }
+
+    /// The calibrated color zone `zone` shows, counting from 1
+    fn zone_color(&self, zone: usize) -> [u8; 3] {
+        self.calibration.zone(zone, self.overlay.zones[zone - 1].unwrap_or(self.kb.zones[zone - 1].color))
+    }
+
+    fn zone_enabled(&self, zone: usize) -> u8 {
+        (self.overlay.zones[zone - 1].is_some() || self.kb.zones[zone - 1].enabled) as u8
+    }
+}
+
+struct State {
+    cfg: Config,
+    static_dev: File,
+    dynamic_dev: File,
+    /// The lighting kept in the config file while the window previews changes to it
+    saved: Option<KBLighting>
+}
+
+impl State {
+    /// Stores a change made outside of the window. The lighting being previewed is saved along with it,
+    /// so that closing the window doesn't throw the change away.
+    fn store(&mut self, config_path: &Path) -> Result<(), confy::ConfyError> {
+        if self.saved.is_some() {
+            self.saved = Some(self.cfg.kb);
+        }
+        store_config(config_path, &self.cfg, self.saved)
+    }
+
+    /// Goes back to the saved lighting if the window was previewing changes to it
+    fn revert_preview(&mut self) {
+        let State { cfg, static_dev, dynamic_dev, saved } = self;
+        if let Some(kb) = saved.take() {
+            if kb != cfg.kb {
+                cfg.kb = kb;
+                apply_lighting(static_dev, dynamic_dev, cfg);
+            }
+        }
+    }
+}
+
+/// Stores the config, keeping `saved` in it instead of the lighting being previewed
+fn store_config(config_path: &Path, cfg: &Config, saved: Option<KBLighting>) -> Result<(), confy::ConfyError> {
+    match saved {
+        Some(kb) => {
+            let mut cfg = cfg.clone();
+            cfg.kb = kb;
+            confy::store_path(config_path, &cfg)
+        }
+        None => confy::store_path(config_path, cfg)
+    }
+}
+
+type SharedState = Arc<Mutex<State>>;
+
+// How many times something was written to the keyboard, so the lighting can be announced as soon as it changes
+static LIGHTING_SHOWN: (Mutex<u64>, Condvar) = (Mutex::new(0), Condvar::new());
+
+fn lighting_shown() {
+    let (count, shown) = &LIGHTING_SHOWN;
+    *count.lock().unwrap() += 1;
+    shown.notify_all();
+}
+
+/// Blocks until something is written to the keyboard after the `seen`th time, updating `seen`
+fn wait_until_shown(seen: &mut u64) {
+    let (count, shown) = &LIGHTING_SHOWN;
+    let count = shown.wait_while(count.lock().unwrap(), |count| *count == *seen).unwrap();
+    *seen = *count;
+}
+
+fn update_dynamic(dynamic_dev: &mut File, cfg: &Config) {
     let mut dynamic_data: [u8; 16] = [0; 16];
     dynamic_data[0] = cfg.kb.effect as u8;
     dynamic_data[1] = cfg.kb.speed;
-    dynamic_data[2] = cfg.kb.brightness;
+    dynamic_data[2] = cfg.brightness();
+    let color = cfg.calibration.dynamic(cfg.kb.color);
     dynamic_data[4] = cfg.kb.direction as u8;
-    dynamic_data[5] = cfg.kb.color[0];
-    dynamic_data[6] = cfg.kb.color[1];
-    dynamic_data[7] = cfg.kb.color[2];
+    dynamic_data[5] = color[0];
+    dynamic_data[6] = color[1];
+    dynamic_data[7] = color[2];
     dynamic_data[9] = 1;
 
-    dynamic_dev.write(&dynamic_data).expect("Failed to write to dynamic device");
+    dynamic_dev.write_all(&dynamic_data).expect("Failed to write to dynamic device");
+    lighting_shown();
 }
 
-fn change_brightness(dynamic_dev: &mut File, cfg: Config) {
+fn change_brightness(dynamic_dev: &mut File, cfg: &Config) {
+    let static_zones = cfg.kb.mode == KBLightMode::Static || cfg.mirroring();
+    match cfg.kb.mode {
+        _ if static_zones => {}
+        KBLightMode::Script => return script::redraw(),
+        KBLightMode::Plugin => return plugins::redraw(),
+        _ => {}
+    }
+
+    let color = cfg.calibration.dynamic(cfg.kb.color);
     let mut dynamic_data: [u8; 16] = [0; 16];
-    dynamic_data[0] = if cfg.kb.mode == KBLightMode::Static {0} else {cfg.kb.effect as u8};
-    dynamic_data[1] = if cfg.kb.mode == KBLightMode::Static {0} else {cfg.kb.speed};
-    dynamic_data[2] = cfg.kb.brightness;
-    dynamic_data[4] = if cfg.kb.mode == KBLightMode::Static {0} else {cfg.kb.direction as u8};
-    dynamic_data[5] = if cfg.kb.mode == KBLightMode::Static {0} else {cfg.kb.color[0]};
-    dynamic_data[6] = if cfg.kb.mode == KBLightMode::Static {0} else {cfg.kb.color[1]};
-    dynamic_data[7] = if cfg.kb.mode == KBLightMode::Static {0} else {cfg.kb.color[2]};
+    dynamic_data[0] = if static_zones {0} else {cfg.kb.effect as u8};
+    dynamic_data[1] = if static_zones {0} else {cfg.kb.speed};
+    dynamic_data[2] = cfg.brightness();
+    dynamic_data[4] = if static_zones {0} else {cfg.kb.direction as u8};
+    dynamic_data[5] = if static_zones {0} else {color[0]};
+    dynamic_data[6] = if static_zones {0} else {color[1]};
+    dynamic_data[7] = if static_zones {0} else {color[2]};
     dynamic_data[9] = 1;
 
-    dynamic_dev.write(&dynamic_data).expect("Failed to write to dynamic device");
+    dynamic_dev.write_all(&dynamic_data).expect("Failed to write to dynamic device");
+    lighting_shown();
 }
 
-fn switch_to_static(static_dev: &mut File, dynamic_dev: &mut File, cfg: Config) {
+fn switch_to_static(static_dev: &mut File, dynamic_dev: &mut File, cfg: &Config) {
     for zone in 1..4 {
         write_to_static_dev(static_dev, cfg, zone);
 
         let mut dynamic_data: [u8; 16] = [0; 16];
-        dynamic_data[2] = cfg.kb.brightness;
+        dynamic_data[2] = cfg.brightness();
         dynamic_data[9] = 1; // This is needed for PT314-52s and possibly other models
 
-        dynamic_dev.write(&dynamic_data).expect("Failed to write to static device");
+        dynamic_dev.write_all(&dynamic_data).expect("Failed to write to static device");
     }
 }
 
-fn write_to_static_dev(static_dev: &mut File, cfg: Config, zone: usize) {
-    let red = cfg.kb.zones[zone - 1].color[0];
-    let green = cfg.kb.zones[zone - 1].color[1];
-    let blue = cfg.kb.zones[zone - 1].color[2];
+fn write_to_static_dev(static_dev: &mut File, cfg: &Config, zone: usize) {
+    let [red, green, blue] = cfg.zone_color(zone);
 
     let static_data: [u8; 8] = [
         0,
@@ -145,280 +362,171 @@ fn write_to_static_dev(static_dev: &mut File, cfg: Config, zone: usize) {
         red,
         green,
         blue,
-        cfg.kb.zones[0].enabled as u8,
-        cfg.kb.zones[1].enabled as u8,
-        cfg.kb.zones[2].enabled as u8
+        cfg.zone_enabled(1),
+        cfg.zone_enabled(2),
+        cfg.zone_enabled(3)
     ];
-    static_dev.write(&static_data).expect("Failed to write to static device");
+    static_dev.write_all(&static_data).expect("Failed to write to static device");
+    lighting_shown();
 }
 
-fn toggle_zone(static_dev: &mut File, cfg: Config, zone_num: usize) {
-    let zone = &cfg.kb.zones[zone_num - 1];
+fn toggle_zone(static_dev: &mut File, cfg: &Config, zone_num: usize) {
+    let color = cfg.zone_color(zone_num);
     let static_data = [
         1, // 0 for setting color, 1 for toggling zones
         zone_num as u8, // Zone number
-        zone.color[0], // R
-        zone.color[1], // G
-        zone.color[2], // B
-        cfg.kb.zones[0].enabled as u8, // Zone 1 enabled
-        cfg.kb.zones[1].enabled as u8, // Zone 2 enabled
-        cfg.kb.zones[2].enabled as u8 // Zone 3 enabled
+        color[0], // R
+        color[1], // G
+        color[2], // B
+        cfg.zone_enabled(1), // Zone 1 enabled
+        cfg.zone_enabled(2), // Zone 2 enabled
+        cfg.zone_enabled(3) // Zone 3 enabled
     ];
 
-    static_dev.write(&static_data).expect("Failed to write to static device");
+    static_dev.write_all(&static_data).expect("Failed to write to static device");
+    lighting_shown();
 }
 
-fn show_dynamic_kb_lighting_pane(ui: &mut egui::Ui, dynamic_dev: &mut File, cfg: &mut Config, config_path: PathBuf) {
-    ui.label("Light Effects");
+// The effects in rows of three
+const EFFECT_COLUMNS: usize = 3;
+
+fn effect_selector(ui: &mut egui::Ui, effect: &mut KBDynamicEffect) {
     egui::Grid::new("Effects")
         .min_col_width(50.0)
         .min_row_height(30.0)
         .show(ui, |ui| {
-            if ui.radio_value(&mut cfg.kb.effect, KBDynamicEffect::Breathing, "Breathing").changed() {
-                update_dynamic(dynamic_dev, *cfg);
-                let _ = confy::store_path(config_path.clone(), &cfg);
-            }
-            if ui.radio_value(&mut cfg.kb.effect, KBDynamicEffect::Shifting, "Shifting").changed() {
-                update_dynamic(dynamic_dev, *cfg);
-                let _ = confy::store_path(config_path.clone(), &cfg);
-            }
-            if ui.radio_value(&mut cfg.kb.effect, KBDynamicEffect::Twinkling, "Twinkling").changed() {
-                update_dynamic(dynamic_dev, *cfg);
-                let _ = confy::store_path(config_path.clone(), &cfg);
-            }
-            ui.end_row();
-            if ui.radio_value(&mut cfg.kb.effect, KBDynamicEffect::Neon, "Neon").changed() {
-                update_dynamic(dynamic_dev, *cfg);
-                let _ = confy::store_path(config_path.clone(), &cfg);
-            }
-            if ui.radio_value(&mut cfg.kb.effect, KBDynamicEffect::Zoom, "Zoom").changed() {
-                update_dynamic(dynamic_dev, *cfg);
-                let _ = confy::store_path(config_path.clone(), &cfg);
-            }
-            ui.end_row();
-            if ui.radio_value(&mut cfg.kb.effect, KBDynamicEffect::Wave, "Wave").changed() {
-                update_dynamic(dynamic_dev, *cfg);
-                let _ = confy::store_path(config_path.clone(), &cfg);
-            }
-            if ui.radio_value(&mut cfg.kb.effect, KBDynamicEffect::Meteor, "Meteor").changed() {
-                update_dynamic(dynamic_dev, *cfg);
-                let _ = confy::store_path(config_path.clone(), &cfg);
+            for (i, option) in KBDynamicEffect::ALL.into_iter().enumerate() {
+                ui.radio_value(effect, option, option.name());
+                if i % EFFECT_COLUMNS == EFFECT_COLUMNS - 1 {
+                    ui.end_row();
+                }
             }
-            ui.end_row();
         });
-    ui.label("Speed");
-    if ui.add(egui::Slider::new(&mut cfg.kb.speed, 1..=9)).changed() {
-        update_dynamic(dynamic_dev, *cfg);
-        let _ = confy::store_path(config_path.clone(), &cfg);
-    }
-    ui.add_space(10.0);
+}
 
-    ui.label("Direction");
-    if ui.radio_value(&mut cfg.kb.direction, KBDynamicDirection::LeftToRight, "Left to Right").changed() {
-        update_dynamic(dynamic_dev, *cfg);
-        let _ = confy::store_path(config_path.clone(), &cfg);
-    }
-    if ui.radio_value(&mut cfg.kb.direction, KBDynamicDirection::RightToLeft, "Right to Left").changed() {
-        update_dynamic(dynamic_dev, *cfg);
-        let _ = confy::store_path(config_path.clone(), &cfg);
+/// Shows the effect settings, returning whether the config should be stored
+fn show_dynamic_kb_lighting_pane(ui: &mut egui::Ui, dynamic_dev: &mut File, cfg: &mut Config) -> bool {
+    let before = cfg.kb;
+
+    if let Some(lighting) = cfg.kb.preview(cfg.effects.capabilities(cfg.kb.effect)) {
+        ui.add(keyboard(lighting, cfg.kb.brightness));
+        ui.add_space(10.0);
     }
+
+    ui.label("Light Effects");
+    effect_selector(ui, &mut cfg.kb.effect);
+    let capabilities = cfg.effects.capabilities(cfg.kb.effect);
+    cfg.kb.speed = capabilities.clamp_speed(cfg.kb.speed);
+
+    ui.label("Speed");
+    ui.add(egui::Slider::new(&mut cfg.kb.speed, capabilities.speeds()));
     ui.add_space(10.0);
 
-    ui.label("Color");
-    ui.horizontal(|ui| {
-        if ui.add(color_box(&mut cfg.kb.color, PresetDynamicColor::RED)).changed() {
-            update_dynamic(dynamic_dev, *cfg);
-            let _ = confy::store_path(config_path.clone(), &cfg);
-        }
-        if ui.add(color_box(&mut cfg.kb.color, PresetDynamicColor::ORANGE)).changed() {
-            update_dynamic(dynamic_dev, *cfg);
-            let _ = confy::store_path(config_path.clone(), &cfg);
-        }
-        if ui.add(color_box(&mut cfg.kb.color, PresetDynamicColor::YELLOW)).changed() {
-            update_dynamic(dynamic_dev, *cfg);
-            let _ = confy::store_path(config_path.clone(), &cfg);
-        }
-        if ui.add(color_box(&mut cfg.kb.color, PresetDynamicColor::GREEN)).changed() {
-            update_dynamic(dynamic_dev, *cfg);
-            let _ = confy::store_path(config_path.clone(), &cfg);
-        }
-        if ui.add(color_box(&mut cfg.kb.color, PresetDynamicColor::BLUE)).changed() {
-            update_dynamic(dynamic_dev, *cfg);
-            let _ = confy::store_path(config_path.clone(), &cfg);
-        }
-        if ui.add(color_box(&mut cfg.kb.color, PresetDynamicColor::INDIGO)).changed() {
-            update_dynamic(dynamic_dev, *cfg);
-            let _ = confy::store_path(config_path.clone(), &cfg);
-        }
-        if ui.add(color_box(&mut cfg.kb.color, PresetDynamicColor::VIOLET)).changed() {
-            update_dynamic(dynamic_dev, *cfg);
-            let _ = confy::store_path(config_path.clone(), &cfg);
-        }
-        if ui.add(color_box(&mut cfg.kb.color, PresetDynamicColor::WHITE)).changed() {
-            update_dynamic(dynamic_dev, *cfg);
-            let _ = confy::store_path(config_path.clone(), &cfg);
+    let mut palette_changed = false;
+    ui.add_enabled_ui(capabilities.direction, |ui| {
+        ui.label("Direction");
+        for (direction, name) in [(KBDynamicDirection::LeftToRight, "Left to Right"), (KBDynamicDirection::RightToLeft, "Right to Left")] {
+            ui.radio_value(&mut cfg.kb.direction, direction, name);
         }
     });
-    ui.vertical(|ui| {
-        ui.label("Custom Color");
-        if ui.color_edit_button_srgb(&mut cfg.kb.color).changed() {
-            update_dynamic(dynamic_dev, *cfg);
-            let _ = confy::store_path(config_path.clone(), &cfg);
-        }
-        ui.horizontal(|ui| {
-            let r = ui.label("R");
-            if ui.add(egui::DragValue::new(&mut cfg.kb.color[0])).labelled_by(r.id).changed() {
-                update_dynamic(dynamic_dev, *cfg);
-                let _ = confy::store_path(config_path.clone(), &cfg);
-            }
-            let g = ui.label("G");
-            if ui.add(egui::DragValue::new(&mut cfg.kb.color[1])).labelled_by(g.id).changed() {
-                update_dynamic(dynamic_dev, *cfg);
-                let _ = confy::store_path(config_path.clone(), &cfg);
-            }
-            let b = ui.label("B");
-            if ui.add(egui::DragValue::new(&mut cfg.kb.color[2])).labelled_by(b.id).changed() {
-                update_dynamic(dynamic_dev, *cfg);
-                let _ = confy::store_path(config_path.clone(), &cfg);
-            }
+    ui.add_space(10.0);
+
+    ui.add_enabled_ui(capabilities.color, |ui| {
+        ui.label("Color");
+        (_, palette_changed) = palette::show(ui, "DynamicPalette", &mut cfg.palette, &mut cfg.kb.color);
+        ui.vertical(|ui| {
+            ui.label("Custom Color");
+            ui.add(color_picker(&mut cfg.kb.color));
+            ui.add(rgb_values(&mut cfg.kb.color));
         });
     });
+
+    if cfg.kb != before {
+        update_dynamic(dynamic_dev, cfg);
+    }
+    if cfg.kb.color != before.color {
+        palette::remember(ui, &mut cfg.palette, cfg.kb.color);
+    }
+
+    cfg.kb != before || palette_changed
 }
 
-fn show_static_kb_lighting_pane(ui: &mut egui::Ui, static_dev: &mut File, cfg: &mut Config, config_path: PathBuf, prohibit_tex: TextureHandle) {
-    ui.horizontal(|ui| {
-        ui.vertical(|ui| {
-            ui.label("Zone 1");
-            ui.horizontal_wrapped(|ui| {
-                let mut picker_rect = ui.available_rect_before_wrap();
+/// Writes the zones that were turned on or off or given another color since `before`
+fn write_changed_zones(static_dev: &mut File, cfg: &Config, before: &KBLighting) {
+    for zone in 1..4 {
+        let (old, new) = (before.zones[zone - 1], cfg.kb.zones[zone - 1]);
+        if old.enabled != new.enabled {
+            toggle_zone(static_dev, cfg, zone);
+        } else if old.color != new.color {
+            write_to_static_dev(static_dev, cfg, zone);
+        }
+    }
+}
 
-                if ui.add(toggle(&mut cfg.kb.zones[0].enabled)).changed() {
-                    toggle_zone(static_dev, *cfg, 1);
-                    let _ = confy::store_path(config_path.clone(), &cfg);
-                }
-                ui.add_enabled_ui(cfg.kb.zones[0].enabled, |ui| {
-                    let picker = ui.color_edit_button_srgb(&mut cfg.kb.zones[0].color);
-                    picker_rect = picker.rect;
-                    if picker.changed() {
-                        write_to_static_dev(static_dev, *cfg, 1);
-                        let _ = confy::store_path(config_path.clone(), &cfg);
-                    }
-                });
-                if !cfg.kb.zones[0].enabled {
-                    let pos = [egui::pos2(picker_rect.max.x - 2.0, picker_rect.max.y - 7.0)];
-                    ui.put(egui::Rect::from_points(&pos), egui::Image::new(&prohibit_tex).max_size(egui::Vec2::new(10.0, 10.0)));
-                }
-            });
-            ui.add_space(10.0);
-            ui.add_enabled_ui(cfg.kb.zones[0].enabled, |ui| {
-                ui.horizontal(|ui| {
-                    let r = ui.label("R");
-                    if ui.add(egui::DragValue::new(&mut cfg.kb.zones[0].color[0])).labelled_by(r.id).changed() {
-                        write_to_static_dev(static_dev, *cfg, 1);
-                        let _ = confy::store_path(config_path.clone(), &cfg);
-                    }
-                    let g = ui.label("G");
-                    if ui.add(egui::DragValue::new(&mut cfg.kb.zones[0].color[1])).labelled_by(g.id).changed() {
-                        write_to_static_dev(static_dev, *cfg, 1);
-                        let _ = confy::store_path(config_path.clone(), &cfg);
-                    }
-                    let b = ui.label("B");
-                    if ui.add(egui::DragValue::new(&mut cfg.kb.zones[0].color[2])).labelled_by(b.id).changed() {
-                        write_to_static_dev(static_dev, *cfg, 1);
-                        let _ = confy::store_path(config_path.clone(), &cfg);
-                    }
-                });
-            })
-        });
-        ui.separator();
-        ui.vertical(|ui| {
-            ui.label("Zone 2");
-            ui.horizontal_wrapped(|ui| {
-                let mut picker_rect = ui.available_rect_before_wrap();
+/// Shows the zones, the palette and the gradient, returning whether the config should be stored
+fn show_static_kb_lighting_pane(ui: &mut egui::Ui, static_dev: &mut File, cfg: &mut Config) -> bool {
+    let before = cfg.kb;
 
-                if ui.add(toggle(&mut cfg.kb.zones[1].enabled)).changed() {
-                    toggle_zone(static_dev, *cfg, 2);
-                    let _ = confy::store_path(config_path.clone(), &cfg);
-                }
-                ui.add_enabled_ui(cfg.kb.zones[1].enabled, |ui| {
-                    let picker = ui.color_edit_button_srgb(&mut cfg.kb.zones[1].color);
-                    picker_rect = picker.rect;
-                    if picker.changed() {
-                        write_to_static_dev(static_dev, *cfg, 2);
-                        let _ = confy::store_path(config_path.clone(), &cfg);
-                    }
-                });
-                if !cfg.kb.zones[1].enabled {
-                    let pos = [egui::pos2(picker_rect.max.x - 2.0, picker_rect.max.y - 7.0)];
-                    ui.put(egui::Rect::from_points(&pos), egui::Image::new(&prohibit_tex).max_size(egui::Vec2::new(10.0, 10.0)));
-                }
-            });
-            ui.add_space(10.0);
-            ui.add_enabled_ui(cfg.kb.zones[1].enabled, |ui| {
-                ui.horizontal(|ui| {
-                    let r = ui.label("R");
-                    if ui.add(egui::DragValue::new(&mut cfg.kb.zones[1].color[0])).labelled_by(r.id).changed() {
-                        write_to_static_dev(static_dev, *cfg, 2);
-                        let _ = confy::store_path(config_path.clone(), &cfg);
-                    }
-                    let g = ui.label("G");
-                    if ui.add(egui::DragValue::new(&mut cfg.kb.zones[1].color[1])).labelled_by(g.id).changed() {
-                        write_to_static_dev(static_dev, *cfg, 2);
-                        let _ = confy::store_path(config_path.clone(), &cfg);
-                    }
-                    let b = ui.label("B");
-                    if ui.add(egui::DragValue::new(&mut cfg.kb.zones[1].color[2])).labelled_by(b.id).changed() {
-                        write_to_static_dev(static_dev, *cfg, 2);
-                        let _ = confy::store_path(config_path.clone(), &cfg);
-                    }
-                });
-            });
-        });
-        ui.separator();
-        ui.vertical(|ui| {
-            ui.label("Zone 3");
-            ui.horizontal_wrapped(|ui| {
-                let mut picker_rect = ui.available_rect_before_wrap();
+    if let Some(lighting) = cfg.kb.preview(cfg.effects.capabilities(cfg.kb.effect)) {
+        ui.add(keyboard(lighting, cfg.kb.brightness));
+        ui.add_space(10.0);
+    }
 
-                if ui.add(toggle(&mut cfg.kb.zones[2].enabled)).changed() {
-                    toggle_zone(static_dev, *cfg, 3);
-                    let _ = confy::store_path(config_path.clone(), &cfg);
-                }
-                ui.add_enabled_ui(cfg.kb.zones[2].enabled, |ui| {
-                    let picker = ui.color_edit_button_srgb(&mut cfg.kb.zones[2].color);
-                    picker_rect = picker.rect;
-                    if picker.changed() {
-                        write_to_static_dev(static_dev, *cfg, 3);
-                        let _ = confy::store_path(config_path.clone(), &cfg);
-                    }
-                });
-                if !cfg.kb.zones[2].enabled {
-                    let pos = [egui::pos2(picker_rect.max.x - 2.0, picker_rect.max.y - 7.0)];
-                    ui.put(egui::Rect::from_points(&pos), egui::Image::new(&prohibit_tex).max_size(egui::Vec2::new(10.0, 10.0)));
+    ui.horizontal(|ui| {
+        for (i, zone) in cfg.kb.zones.iter_mut().enumerate() {
+            if i > 0 {
+                ui.separator();
+            }
+            ui.add(zone_editor(&format!("Zone {}", i + 1), &mut zone.enabled, &mut zone.color));
+        }
+    });
+    ui.add_space(10.0);
+
+    // Which zones a swatch is given to, all of them when None
+    let target_id = egui::Id::new("PaletteZone");
+    let mut target: Option<usize> = ui.data(|data| data.get_temp(target_id)).flatten();
+    ui.horizontal(|ui| {
+        ui.label("Palette for");
+        egui::ComboBox::from_id_source("PaletteZone")
+            .selected_text(target.map_or("All zones".to_string(), |zone| format!("Zone {}", zone + 1)))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut target, None, "All zones");
+                for zone in 0..3 {
+                    ui.selectable_value(&mut target, Some(zone), format!("Zone {}", zone + 1));
                 }
             });
-            ui.add_space(10.0);
-            ui.add_enabled_ui(cfg.kb.zones[2].enabled, |ui| {
-                ui.horizontal(|ui| {
-                    let r = ui.label("R");
-                    if ui.add(egui::DragValue::new(&mut cfg.kb.zones[2].color[0])).labelled_by(r.id).changed() {
-                        write_to_static_dev(static_dev, *cfg, 3);
-                        let _ = confy::store_path(config_path.clone(), &cfg);
-                    }
-                    let g = ui.label("G");
-                    if ui.add(egui::DragValue::new(&mut cfg.kb.zones[2].color[1])).labelled_by(g.id).changed() {
-                        write_to_static_dev(static_dev, *cfg, 3);
-                        let _ = confy::store_path(config_path.clone(), &cfg);
-                    }
-                    let b = ui.label("B");
-                    if ui.add(egui::DragValue::new(&mut cfg.kb.zones[2].color[2])).labelled_by(b.id).changed() {
-                        write_to_static_dev(static_dev, *cfg, 3);
-                        let _ = confy::store_path(config_path.clone(), &cfg);
-                    }
-                });
-            });
-        });
     });
+    ui.data_mut(|data| data.insert_temp(target_id, target));
+
+    let mut color = cfg.kb.zones[target.unwrap_or(0)].color;
+    let (picked, palette_changed) = palette::show(ui, "StaticPalette", &mut cfg.palette, &mut color);
+    if picked {
+        for (i, zone) in cfg.kb.zones.iter_mut().enumerate() {
+            if target.is_none_or(|target| target == i) {
+                zone.color = color;
+            }
+        }
+    }
+
+    let mut gradient_changed = false;
+    egui::CollapsingHeader::new("Gradient Fill").show(ui, |ui| {
+        let (changed, fill) = gradient::show(ui, &mut cfg.gradient);
+        if fill {
+            for (zone, color) in cfg.kb.zones.iter_mut().zip(cfg.gradient.zones()) {
+                zone.color = color;
+            }
+        }
+        gradient_changed = changed;
+    });
+
+    write_changed_zones(static_dev, cfg, &before);
+    for (zone, old) in cfg.kb.zones.into_iter().zip(before.zones) {
+        if zone.color != old.color {
+            palette::remember(ui, &mut cfg.palette, zone.color);
+        }
+    }
+
+    cfg.kb != before || palette_changed || gradient_changed
 }
 
 fn check_devices(options: &eframe::NativeOptions) -> Result<(File, File), String> {
@@ -432,40 +540,230 @@ fn check_devices(options: &eframe::NativeOptions) -> Result<(File, File), String
         .create(false)
         .open("/dev/acer-gkbbl-0");
 
-    if static_dev.is_err() || dynamic_dev.is_err() {
-        eprintln!("[ERROR]: Could not open device files");
-
-        let _ = eframe::run_simple_native("Predator-ng", options.clone(), move |ctx, _frame| {
-            egui::CentralPanel::default().show(ctx, |ui| {
-                ui.colored_label(egui::Color32::RED, egui::RichText::new("Error: could not open device files").heading());
-                ui.horizontal_wrapped(|ui| {
-                    ui.label("Please make sure you have the kernel module loaded, and that both");
-                    ui.label(egui::RichText::new("/dev/acer-gkbbl-0").code());
-                    ui.label("and");
-                    ui.label(egui::RichText::new("/dev/acer-gkbbl-static-0").code());
-                    ui.label("exist");
+    match (static_dev, dynamic_dev) {
+        (Ok(static_dev), Ok(dynamic_dev)) => Ok((static_dev, dynamic_dev)),
+        _ => {
+            eprintln!("[ERROR]: Could not open device files");
+
+            let _ = eframe::run_simple_native("Predator-ng", options.clone(), move |ctx, _frame| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    ui.colored_label(egui::Color32::RED, egui::RichText::new("Error: could not open device files").heading());
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label("Please make sure you have the kernel module loaded, and that both");
+                        ui.label(egui::RichText::new("/dev/acer-gkbbl-0").code());
+                        ui.label("and");
+                        ui.label(egui::RichText::new("/dev/acer-gkbbl-static-0").code());
+                        ui.label("exist");
+                    });
                 });
             });
-        });
 
-        return Err("Could not open device files".to_string());
-    } else {
-        return Ok((static_dev.unwrap(), dynamic_dev.unwrap()));
+            Err("Could not open device files".to_string())
+        }
     }
 }
 
+fn apply_lighting(static_dev: &mut File, dynamic_dev: &mut File, cfg: &Config) {
+    match cfg.kb.mode {
+        KBLightMode::Static => switch_to_static(static_dev, dynamic_dev, cfg),
+        _ if cfg.mirroring() => switch_to_static(static_dev, dynamic_dev, cfg),
+        KBLightMode::Dynamic => update_dynamic(dynamic_dev, cfg),
+        KBLightMode::Script => script::redraw(),
+        KBLightMode::Plugin => plugins::redraw()
+    }
+}
+
+/// The XDG base directory in `variable`, or `fallback` in the home directory
+fn xdg_dir(variable: &str, fallback: &str) -> Option<PathBuf> {
+    var(variable).ok().map(PathBuf::from)
+        .or_else(|| var("HOME").ok().map(|home| Path::new(&home).join(fallback)))
+}
+
+// Older versions kept the config in a file where the config directory is now
+fn move_old_config(config_dir: &Path, config_path: &Path) -> std::io::Result<()> {
+    let old_path = config_dir.with_extension("old");
+    std::fs::rename(config_dir, &old_path)?;
+    std::fs::create_dir_all(config_dir)?;
+    std::fs::rename(old_path, config_path)
+}
+
 fn initial_load(config_path: PathBuf, static_dev: &mut File, dynamic_dev: &mut File) -> Result<Config, confy::ConfyError> {
-    let cfg: Config = confy::load_path(config_path.clone())?;
+    let cfg: Config = confy::load_path(config_path)?;
 
-    if cfg.kb.mode == KBLightMode::Static {
-        switch_to_static(static_dev, dynamic_dev, cfg);
-    } else {
-        update_dynamic(dynamic_dev, cfg);
-    }
+    apply_lighting(static_dev, dynamic_dev, &cfg);
 
     Ok(cfg)
 }
 
+// Shows a combo box for choosing one of the saved profiles, or none at all
+fn profile_picker(ui: &mut egui::Ui, id: &str, profiles: &[Profile], selected: &mut Option<String>) -> bool {
+    let mut changed = false;
+
+    egui::ComboBox::from_id_source(id)
+        .selected_text(selected.as_deref().unwrap_or("None"))
+        .show_ui(ui, |ui| {
+            changed |= ui.selectable_value(selected, None, "None").changed();
+            for profile in profiles {
+                changed |= ui.selectable_value(selected, Some(profile.name.clone()), &profile.name).changed();
+            }
+        });
+
+    changed
+}
+
+fn show_profiles(ui: &mut egui::Ui, state: &SharedState, cfg: &mut Config, saved: Option<KBLighting>, config_path: PathBuf, new_profile_name: &mut String) {
+    ui.horizontal(|ui| {
+        ui.label("Profile: ");
+        egui::ComboBox::from_id_source("Profiles")
+            .selected_text("Apply...")
+            .show_ui(ui, |ui| {
+                for i in 0..cfg.profiles.len() {
+                    if ui.selectable_label(false, &cfg.profiles[i].name).clicked() {
+                        // The transition applies the profile to the state once the frame releases it
+                        let mut target = cfg.clone();
+                        target.kb = cfg.profiles[i].kb;
+                        let _ = store_config(&config_path, &target, saved);
+                        fade::spawn_transition(state.clone(), target.kb);
+                    }
+                }
+            });
+        ui.add(egui::TextEdit::singleline(new_profile_name).hint_text("Profile name").desired_width(120.0));
+        let name = new_profile_name.trim().to_string();
+        if ui.add_enabled(!name.is_empty(), egui::Button::new("Save")).clicked() {
+            let kb = cfg.kb;
+            match cfg.profiles.iter_mut().find(|profile| profile.name == name) {
+                Some(profile) => profile.kb = kb,
+                None => cfg.profiles.push(Profile { name: name.clone(), kb })
+            }
+            let _ = store_config(&config_path, cfg, saved);
+        }
+        if ui.add_enabled(cfg.profile(&name).is_some(), egui::Button::new("Delete")).clicked() {
+            cfg.profiles.retain(|profile| profile.name != name);
+            let _ = store_config(&config_path, cfg, saved);
+        }
+    });
+}
+
+/// What the settings below the lighting panes get to change
+struct SettingsContext<'a> {
+    state: &'a SharedState,
+    static_dev: &'a mut File,
+    dynamic_dev: &'a mut File,
+    cfg: &'a mut Config
+}
+
+/// A collapsible section of settings, which shows them and returns whether the config should be stored
+type SettingsSection = (&'static str, fn(&mut egui::Ui, &mut SettingsContext) -> bool);
+
+const SETTINGS_SECTIONS: [SettingsSection; 15] = [
+    ("Power Source", |ui, settings| power::show_settings(ui, settings.cfg)),
+    ("Brightness", |ui, settings| fade::show_settings(ui, settings.cfg)),
+    ("Calibration", |ui, settings| calibration::show_settings(ui, settings.static_dev, settings.dynamic_dev, settings.cfg)),
+    ("Effects", |ui, settings| effects::show_settings(ui, settings.cfg)),
+    ("Schedule", |ui, settings| schedule::show_settings(ui, settings.cfg)),
+    ("Apps", |ui, settings| apps::show_settings(ui, settings.cfg)),
+    ("Accent Color", |ui, settings| accent::show_settings(ui, settings.cfg)),
+    ("Wallpaper Colors", |ui, settings| wallpaper::show_settings(ui, settings.static_dev, settings.dynamic_dev, settings.cfg)),
+    ("Notifications", |ui, settings| notify::show_settings(ui, settings.state, settings.cfg)),
+    ("Tray", |ui, settings| tray::show_settings(ui, settings.cfg)),
+    ("Hotkeys", |ui, settings| hotkeys::show_settings(ui, settings.cfg)),
+    ("OpenRGB", |ui, settings| openrgb::show_settings(ui, settings.cfg)),
+    ("MQTT", |ui, settings| mqtt::show_settings(ui, settings.cfg)),
+    ("HTTP API", |ui, settings| api::show_settings(ui, settings.cfg)),
+    ("Idle Timeout", |ui, settings| idle::show_settings(ui, settings.cfg))
+];
+
+/// Shows the window until it's closed
+fn show_window(options: eframe::NativeOptions, state: SharedState, config_path: PathBuf) {
+    let mut new_profile_name = String::new();
+    let mut lighting_history = history::History::default();
+
+    let _ = eframe::run_simple_native("Predator-ng", options, move |ctx, _frame| {
+        let shared_state = state.clone();
+        let mut state = state.lock().unwrap();
+        let State { cfg, static_dev, dynamic_dev, saved } = &mut *state;
+
+        // Background threads may change the lighting at any time
+        ctx.request_repaint_after(std::time::Duration::from_secs(1));
+
+        // Only the changes made by the widgets below the history bar can be undone,
+        // changes from outside the window are previewed like any other
+        let mut kb_before = cfg.kb;
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                if history::show(ui, &mut lighting_history, static_dev, dynamic_dev, cfg, saved) {
+                    let _ = store_config(&config_path, cfg, *saved);
+                }
+                kb_before = cfg.kb;
+                ui.horizontal(|ui| {
+                    ui.label("Keyboard Lighting Mode: ");
+                    for mode in KBLightMode::ALL {
+                        if ui.radio_value(&mut cfg.kb.mode, mode, mode.name()).clicked() {
+                            apply_lighting(static_dev, dynamic_dev, cfg);
+                            let _ = store_config(&config_path, cfg, *saved);
+                        }
+                    }
+                    ui.label("Keyboard Brightness: ");
+                    let mut on = cfg.kb.brightness > 0;
+                    if ui.add(toggle(&mut on)).changed() {
+                        let to = if on { control::lit_brightness() } else { 0 };
+                        let mut target = cfg.clone();
+                        target.kb.brightness = to;
+                        let _ = store_config(&config_path, &target, *saved);
+                        fade::spawn_fade_brightness(shared_state.clone(), to);
+                    }
+                    control::remember_brightness(cfg.kb.brightness);
+                    if ui.add(egui::Slider::new(&mut cfg.kb.brightness, 0..=100).show_value(false).step_by(cfg.fade.step() as f64)).changed() {
+                        change_brightness(dynamic_dev, cfg);
+                        let _ = store_config(&config_path, cfg, *saved);
+                    }
+                });
+                show_profiles(ui, &shared_state, cfg, *saved, config_path.clone(), &mut new_profile_name);
+                ui.add_space(15.0);
+                ui.group(|ui| {
+                    match cfg.kb.mode {
+                        KBLightMode::Static => {
+                            if show_static_kb_lighting_pane(ui, static_dev, cfg) {
+                                let _ = store_config(&config_path, cfg, *saved);
+                            }
+                        },
+                        KBLightMode::Dynamic => {
+                            if show_dynamic_kb_lighting_pane(ui, dynamic_dev, cfg) {
+                                let _ = store_config(&config_path, cfg, *saved);
+                            }
+                        },
+                        KBLightMode::Script => {
+                            if script::show_settings(ui, cfg, &config_path) {
+                                let _ = store_config(&config_path, cfg, *saved);
+                            }
+                        },
+                        KBLightMode::Plugin => {
+                            if plugins::show_settings(ui, cfg, &config_path) {
+                                let _ = store_config(&config_path, cfg, *saved);
+                            }
+                        }
+                    }
+                });
+                ui.add_space(15.0);
+                let mut settings = SettingsContext { state: &shared_state, static_dev, dynamic_dev, cfg };
+                for (title, show) in SETTINGS_SECTIONS {
+                    egui::CollapsingHeader::new(title).show(ui, |ui| {
+                        if show(ui, &mut settings) {
+                            let _ = store_config(&config_path, settings.cfg, *saved);
+                        }
+                    });
+                }
+            });
+        });
+
+        lighting_history.record(ctx, kb_before, cfg.kb);
+
+        if calibration::show_wizard(ctx, static_dev, dynamic_dev, cfg) {
+            let _ = store_config(&config_path, cfg, *saved);
+        }
+    });
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(640.0, 480.0)),
@@ -475,48 +773,46 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     match check_devices(&options) {
         Ok((mut static_dev, mut dynamic_dev)) => {
-            let config_home = var("XDG_CONFIG_HOME")
-                .or_else(|_| var("HOME").map(|home|format!("{}/.config", home))).unwrap();
-
-            let config_path = Path::new(&config_home).join("predator-ng").to_path_buf();
-            let mut cfg = initial_load(config_path.clone(), &mut static_dev, &mut dynamic_dev)?;
+            let config_dir = xdg_dir("XDG_CONFIG_HOME", ".config").unwrap().join("predator-ng");
+            let config_path = config_dir.join("config.ron");
+            if config_dir.is_file() {
+                move_old_config(&config_dir, &config_path)?;
+            }
+            let cfg = initial_load(config_path.clone(), &mut static_dev, &mut dynamic_dev)?;
+
+            let saved = cfg.history.preview.then_some(cfg.kb);
+            let state: SharedState = Arc::new(Mutex::new(State { cfg, static_dev, dynamic_dev, saved }));
+            power::spawn(state.clone());
+            schedule::spawn(state.clone());
+            apps::spawn(state.clone());
+            accent::spawn(state.clone());
+            wallpaper::spawn(state.clone());
+            notify::spawn(state.clone());
+            dbus::spawn(state.clone(), config_path.clone());
+            openrgb::spawn(state.clone(), config_path.clone());
+            mqtt::spawn(state.clone(), config_path.clone());
+            api::spawn(state.clone(), config_path.clone());
+            script::spawn(state.clone(), config_path.clone());
+            plugins::spawn(state.clone(), config_path.clone());
+            let tray_running = tray::spawn(state.clone(), config_path.clone());
+            let input_state = state.clone();
+            input::spawn(vec![idle::spawn(state.clone()), hotkeys::handler(state.clone(), config_path.clone())], move || {
+                let cfg = &input_state.lock().unwrap().cfg;
+                cfg.idle.enabled || cfg.hotkeys.enabled
+            });
 
-            let prohibit_svg_bytes = include_bytes!("../assets/prohibit.svg");
+            loop {
+                show_window(options.clone(), state.clone(), config_path.clone());
 
-            let _ = eframe::run_simple_native("Predator-ng", options, move |ctx, _frame| {
-                let prohibit_svg = image::load_svg_bytes(prohibit_svg_bytes).expect("Failed to load prohibit icon");
-                let prohibit_tex = ctx.load_texture("prohibit", prohibit_svg, Default::default());
+                // Lighting that was only previewed goes back to the saved one with the window
+                state.lock().unwrap().revert_preview();
 
-                egui::CentralPanel::default().show(ctx, |ui| {
-                    ui.horizontal(|ui| {
-                        ui.label("Keyboard Lighting Mode: ");
-                        if ui.radio_value(&mut cfg.kb.mode, KBLightMode::Static, "Static").clicked() {
-                            switch_to_static(&mut static_dev, &mut dynamic_dev, cfg);
-                            let _ = confy::store_path(config_path.clone(), &cfg);
-                        }
-                        if ui.radio_value(&mut cfg.kb.mode, KBLightMode::Dynamic, "Dynamic").clicked() {
-                            update_dynamic(&mut dynamic_dev, cfg);
-                            let _ = confy::store_path(config_path.clone(), &cfg);
-                        }
-                        ui.label("Keyboard Brightness: ");
-                        if ui.add(egui::Slider::new(&mut cfg.kb.brightness, 0..=100).show_value(false).step_by(25.0)).changed() {
-                            change_brightness(&mut dynamic_dev, cfg);
-                            let _ = confy::store_path(config_path.clone(), &cfg);
-                        }
-                    });
-                    ui.add_space(15.0);
-                    ui.group(|ui| {
-                        match cfg.kb.mode {
-                            KBLightMode::Static => {
-                                show_static_kb_lighting_pane(ui, &mut static_dev, &mut cfg, config_path.clone(), prohibit_tex);
-                            },
-                            KBLightMode::Dynamic => {
-                                show_dynamic_kb_lighting_pane(ui, &mut dynamic_dev, &mut cfg, config_path.clone());
-                            }
-                        }
-                    });
-                });
-            });
+                // Keep controlling the keyboard from the tray until "Quit" is chosen there, or the window is shown again
+                if !tray_running {
+                    break;
+                }
+                tray::wait_for_show();
+            }
 
             Ok(())
         }
@@ -0,0 +1,270 @@
+use std::{path::{Path, PathBuf}, thread, time::Duration};
+
+use eframe::egui;
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{Config, KBDynamicEffect, KBLightMode, SharedState, control, wait_until_shown};
+
+const DISCOVERY_PREFIX: &str = "homeassistant";
+// Name Home Assistant shows for the keyboard's mode, next to the firmware effects
+const STATIC_EFFECT: &str = "Static";
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct MqttSettings {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    /// The topics are `<topic>/state`, `<topic>/availability` and `<topic>/set/...`
+    pub topic: String,
+    pub discovery: bool
+}
+
+impl Default for MqttSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "localhost".to_string(),
+            port: 1883,
+            username: String::new(),
+            password: String::new(),
+            topic: "predator-ng".to_string(),
+            discovery: true
+        }
+    }
+}
+
+impl MqttSettings {
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.topic, name)
+    }
+}
+
+/// Describes the keyboard as a light to Home Assistant, reading everything from the state topic
+fn discovery_config(settings: &MqttSettings) -> String {
+    let mut effects = vec![STATIC_EFFECT];
+    effects.extend(KBDynamicEffect::ALL.map(|effect| effect.name()));
+
+    json!({
+        "name": "Keyboard",
+        "unique_id": format!("{}-keyboard", settings.topic),
+        "device": {
+            "identifiers": [settings.topic],
+            "name": "Predator keyboard",
+            "manufacturer": "Acer",
+            "sw_version": env!("CARGO_PKG_VERSION")
+        },
+        "availability_topic": settings.topic("availability"),
+        "command_topic": settings.topic("set/power"),
+        "state_topic": settings.topic("state"),
+        "state_value_template": "{{ 'ON' if value_json.brightness > 0 else 'OFF' }}",
+        "brightness_command_topic": settings.topic("set/brightness"),
+        "brightness_state_topic": settings.topic("state"),
+        "brightness_value_template": "{{ value_json.brightness }}",
+        "brightness_scale": 100,
+        "rgb_command_topic": settings.topic("set/color"),
+        "rgb_state_topic": settings.topic("state"),
+        "rgb_value_template": "{{ (value_json.color if value_json.mode == 'Dynamic' else value_json.zones[0].color) | join(',') }}",
+        "effect_command_topic": settings.topic("set/effect"),
+        "effect_state_topic": settings.topic("state"),
+        "effect_value_template": format!("{{{{ value_json.effect if value_json.mode == 'Dynamic' else '{}' }}}}", STATIC_EFFECT),
+        "effect_list": effects
+    }).to_string()
+}
+
+/// Parses `r,g,b`
+pub fn parse_color(payload: &str) -> Option<[u8; 3]> {
+    let channels: Vec<u8> = payload.split(',').map(|channel| channel.trim().parse().ok()).collect::<Option<_>>()?;
+    channels.try_into().ok()
+}
+
+fn perform(state: &SharedState, config_path: &Path, command: &str, payload: &str) {
+    match command {
+        "power" => match payload {
+            "ON" if state.lock().unwrap().cfg.kb.brightness == 0 => control::set_brightness(state, control::lit_brightness()),
+            "OFF" => control::set_brightness(state, 0),
+            _ => return
+        },
+        "brightness" => match payload.trim().parse() {
+            Ok(level) => control::set_brightness(state, level),
+            Err(_) => return
+        },
+        "color" => match parse_color(payload).map(|color| control::set_color(state, color)) {
+            Some(Ok(())) => {}
+            Some(Err(e)) => {
+                eprintln!("[ERROR]: Could not set the color over MQTT: {}", e);
+                return;
+            }
+            None => return
+        },
+        "effect" => match KBDynamicEffect::ALL.into_iter().find(|effect| effect.name() == payload) {
+            Some(effect) => control::set_effect(state, effect),
+            None if payload == STATIC_EFFECT => control::set_mode(state, KBLightMode::Static),
+            None => return
+        },
+        "profile" => {
+            if !control::apply_profile(state, payload) {
+                return;
+            }
+        }
+        _ => return
+    }
+
+    control::save(state, config_path);
+}
+
+/// Announces the keyboard and listens for commands every time the connection is made
+fn on_connect(client: &mut Client, settings: &MqttSettings, state: &SharedState) -> Result<(), rumqttc::ClientError> {
+    client.try_subscribe(settings.topic("set/+"), QoS::AtLeastOnce)?;
+    client.try_publish(settings.topic("availability"), QoS::AtLeastOnce, true, "online")?;
+
+    if settings.discovery {
+        let topic = format!("{}/light/{}/keyboard/config", DISCOVERY_PREFIX, settings.topic);
+        client.try_publish(topic, QoS::AtLeastOnce, true, discovery_config(settings))?;
+    }
+
+    let kb = state.lock().unwrap().cfg.kb;
+    client.try_publish(settings.topic("state"), QoS::AtLeastOnce, true, serde_json::to_string(&kb).unwrap())
+}
+
+/// Publishes the lighting whenever it changes, including changes made from predator-ng itself
+fn publish_state(mut client: Client, settings: MqttSettings, state: SharedState) {
+    let mut published = state.lock().unwrap().cfg.kb;
+    let mut seen = 0;
+
+    loop {
+        wait_until_shown(&mut seen);
+
+        let kb = state.lock().unwrap().cfg.kb;
+        if kb != published {
+            if let Err(e) = client.publish(settings.topic("state"), QoS::AtLeastOnce, true, serde_json::to_string(&kb).unwrap()) {
+                eprintln!("[ERROR]: Could not publish the lighting over MQTT: {}", e);
+                return;
+            }
+            published = kb;
+        }
+    }
+}
+
+/// Connects to the MQTT broker if it's enabled, reconnecting whenever the connection is lost
+pub fn spawn(state: SharedState, config_path: PathBuf) {
+    let settings = state.lock().unwrap().cfg.mqtt.clone();
+    if !settings.enabled {
+        return;
+    }
+
+    let mut options = MqttOptions::new(settings.topic.clone(), settings.host.clone(), settings.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(settings.topic("availability"), "offline", QoS::AtLeastOnce, true));
+    if !settings.username.is_empty() {
+        options.set_credentials(settings.username.clone(), settings.password.clone());
+    }
+
+    let (mut client, mut connection) = Client::new(options, 16);
+    {
+        let client = client.clone();
+        let settings = settings.clone();
+        let state = state.clone();
+        thread::spawn(move || publish_state(client, settings, state));
+    }
+
+    thread::spawn(move || {
+        let command_prefix = settings.topic("set/");
+
+        for event in connection.iter() {
+            match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    if let Err(e) = on_connect(&mut client, &settings, &state) {
+                        eprintln!("[ERROR]: Could not set up MQTT: {}", e);
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    let Some(command) = publish.topic.strip_prefix(&command_prefix).map(str::to_string) else {
+                        continue;
+                    };
+                    let payload = String::from_utf8_lossy(&publish.payload).to_string();
+
+                    let state = state.clone();
+                    let config_path = config_path.clone();
+                    thread::spawn(move || perform(&state, &config_path, &command, &payload));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("[ERROR]: Lost the connection to the MQTT broker, retrying: {}", e);
+                    thread::sleep(Duration::from_secs(5));
+                }
+            }
+        }
+    });
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config) -> bool {
+    let settings = &mut cfg.mqtt;
+
+    let mut changed = ui.checkbox(&mut settings.enabled, "Connect to an MQTT broker")
+        .on_hover_text("Takes effect after restarting predator-ng")
+        .changed();
+    ui.add_enabled_ui(settings.enabled, |ui| {
+        egui::Grid::new("Mqtt").num_columns(2).show(ui, |ui| {
+            ui.label("Broker");
+            ui.horizontal(|ui| {
+                changed |= ui.text_edit_singleline(&mut settings.host).changed();
+                changed |= ui.add(egui::DragValue::new(&mut settings.port)).changed();
+            });
+            ui.end_row();
+
+            ui.label("Username");
+            changed |= ui.add(egui::TextEdit::singleline(&mut settings.username).hint_text("None")).changed();
+            ui.end_row();
+
+            ui.label("Password");
+            changed |= ui.add(egui::TextEdit::singleline(&mut settings.password).password(true)).changed();
+            ui.end_row();
+
+            ui.label("Topic");
+            changed |= ui.text_edit_singleline(&mut settings.topic).changed();
+            ui.end_row();
+        });
+        changed |= ui.checkbox(&mut settings.discovery, "Announce the keyboard to Home Assistant").changed();
+    });
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("255,128,0"), Some([255, 128, 0]));
+        assert_eq!(parse_color(" 1, 2 ,3 "), Some([1, 2, 3]));
+        assert_eq!(parse_color("1,2"), None);
+        assert_eq!(parse_color("1,2,3,4"), None);
+        assert_eq!(parse_color("1,2,256"), None);
+        assert_eq!(parse_color("red"), None);
+    }
+
+    #[test]
+    fn describes_the_keyboard_to_home_assistant() {
+        let settings = MqttSettings { topic: "office/keyboard".to_string(), ..Default::default() };
+        let config: Value = serde_json::from_str(&discovery_config(&settings)).unwrap();
+
+        assert_eq!(config["unique_id"], "office/keyboard-keyboard");
+        assert_eq!(config["command_topic"], "office/keyboard/set/power");
+        assert_eq!(config["availability_topic"], "office/keyboard/availability");
+        assert_eq!(config["rgb_command_topic"], "office/keyboard/set/color");
+        assert_eq!(config["brightness_scale"], 100);
+
+        let effects = config["effect_list"].as_array().unwrap();
+        assert_eq!(effects[0], STATIC_EFFECT);
+        assert_eq!(effects.len(), KBDynamicEffect::ALL.len() + 1);
+        assert!(KBDynamicEffect::ALL.iter().all(|effect| effects.contains(&Value::from(effect.name()))));
+    }
+}
@@ -0,0 +1,256 @@
+use std::{collections::HashMap, thread, time::Duration};
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use zbus::{MessageType, blocking::{Connection, MessageIterator}, zvariant::OwnedValue};
+
+use crate::{Config, KBLightMode, KBLighting, State, SharedState, Zone, apply_lighting, switch_to_static};
+
+const NOTIFY_MATCH_RULE: &str = "type='method_call',interface='org.freedesktop.Notifications',member='Notify'";
+
+type NotifyArgs = (String, u32, String, String, String, Vec<String>, HashMap<String, OwnedValue>, i32);
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FlashRule {
+    /// Matches notifications whose app name contains this, or any app if empty
+    pub app_name: String,
+    /// Matches notifications whose summary contains this, or any summary if empty
+    pub summary: String,
+    pub colors: Vec<[u8; 3]>,
+    pub duration: u64,
+    pub repeat: u8
+}
+
+impl Default for FlashRule {
+    fn default() -> Self {
+        Self {
+            app_name: String::new(),
+            summary: String::new(),
+            colors: vec![[255, 0, 0], [0, 0, 0]],
+            duration: 200,
+            repeat: 3
+        }
+    }
+}
+
+impl FlashRule {
+    fn matches(&self, app_name: &str, summary: &str) -> bool {
+        let contains = |haystack: &str, needle: &str| haystack.to_lowercase().contains(&needle.to_lowercase());
+
+        contains(app_name, &self.app_name) && contains(summary, &self.summary)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct NotificationSettings {
+    pub enabled: bool,
+    pub rules: Vec<FlashRule>
+}
+
+/// Flashes every zone through the rule's colors at the current brightness and then restores the previous lighting
+pub fn flash(state: &SharedState, rule: &FlashRule) {
+    for _ in 0..rule.repeat {
+        for color in &rule.colors {
+            {
+                let mut state = state.lock().unwrap();
+                let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+
+                let mut flash_cfg = cfg.clone();
+                flash_cfg.kb = KBLighting {
+                    mode: KBLightMode::Static,
+                    zones: [Zone { color: *color, enabled: true }; 3],
+                    ..cfg.kb
+                };
+                switch_to_static(static_dev, dynamic_dev, &flash_cfg);
+            }
+
+            thread::sleep(Duration::from_millis(rule.duration));
+        }
+    }
+
+    let mut state = state.lock().unwrap();
+    let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+    apply_lighting(static_dev, dynamic_dev, cfg);
+}
+
+/// Has the bus pass a copy of each notification to `conn`, which can't be used for anything else after that
+fn become_monitor(conn: &Connection) -> zbus::Result<MessageIterator> {
+    let messages = MessageIterator::from(conn);
+
+    conn.call_method(
+        Some("org.freedesktop.DBus"),
+        "/org/freedesktop/DBus",
+        Some("org.freedesktop.DBus.Monitoring"),
+        "BecomeMonitor",
+        &(&[NOTIFY_MATCH_RULE] as &[&str], 0u32)
+    )?;
+
+    Ok(messages)
+}
+
+fn watch(messages: MessageIterator, state: &SharedState) -> zbus::Result<()> {
+    for msg in messages {
+        let msg = msg?;
+        if msg.message_type() != MessageType::MethodCall || msg.member().as_deref() != Some("Notify") {
+            continue;
+        }
+        let Ok((app_name, _, _, summary, ..)) = msg.body::<NotifyArgs>() else {
+            continue;
+        };
+
+        let settings = state.lock().unwrap().cfg.notifications.clone();
+        if !settings.enabled {
+            continue;
+        }
+        if let Some(rule) = settings.rules.iter().find(|rule| rule.matches(&app_name, &summary)) {
+            flash(state, rule);
+        }
+    }
+
+    Ok(())
+}
+
+fn monitor(state: &SharedState) -> zbus::Result<()> {
+    let conn = Connection::session()?;
+    watch(become_monitor(&conn)?, state)
+}
+
+/// Watches the session bus for notifications. `DBUS_SESSION_BUS_ADDRESS` can point this at a private bus.
+pub fn spawn(state: SharedState) {
+    thread::spawn(move || {
+        if let Err(e) = monitor(&state) {
+            eprintln!("[ERROR]: Could not monitor desktop notifications: {}", e);
+        }
+    });
+}
+
+pub fn show_settings(ui: &mut egui::Ui, state: &SharedState, cfg: &mut Config) -> bool {
+    let settings = &mut cfg.notifications;
+
+    let mut changed = ui.checkbox(&mut settings.enabled, "Flash the keyboard on desktop notifications").changed();
+    ui.add_enabled_ui(settings.enabled, |ui| {
+        let mut removed = None;
+        for (i, rule) in settings.rules.iter_mut().enumerate() {
+            ui.separator();
+            egui::Grid::new(format!("FlashRule{}", i)).num_columns(2).show(ui, |ui| {
+                ui.label("App name");
+                changed |= ui.add(egui::TextEdit::singleline(&mut rule.app_name).hint_text("Any app")).changed();
+                ui.end_row();
+
+                ui.label("Summary");
+                changed |= ui.add(egui::TextEdit::singleline(&mut rule.summary).hint_text("Any summary")).changed();
+                ui.end_row();
+
+                ui.label("Colors");
+                ui.horizontal(|ui| {
+                    let mut removed_color = None;
+                    for (j, color) in rule.colors.iter_mut().enumerate() {
+                        let picker = ui.color_edit_button_srgb(color);
+                        changed |= picker.changed();
+                        if picker.secondary_clicked() {
+                            removed_color = Some(j);
+                        }
+                    }
+                    if let Some(j) = removed_color {
+                        rule.colors.remove(j);
+                        changed = true;
+                    }
+                    if ui.small_button("+").clicked() {
+                        rule.colors.push([255, 255, 255]);
+                        changed = true;
+                    }
+                }).response.on_hover_text("Right click a color to remove it");
+                ui.end_row();
+
+                ui.label("Duration");
+                changed |= ui.add(egui::DragValue::new(&mut rule.duration).clamp_range(50..=2000).suffix(" ms")).changed();
+                ui.end_row();
+
+                ui.label("Repeat");
+                changed |= ui.add(egui::DragValue::new(&mut rule.repeat).clamp_range(1..=20)).changed();
+                ui.end_row();
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Test").clicked() {
+                    let state = state.clone();
+                    let rule = rule.clone();
+                    thread::spawn(move || flash(&state, &rule));
+                }
+                if ui.button("Remove").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(i) = removed {
+            settings.rules.remove(i);
+            changed = true;
+        }
+
+        ui.separator();
+        if ui.button("Add rule").clicked() {
+            settings.rules.push(Default::default());
+            changed = true;
+        }
+    });
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Instant};
+
+    use zbus::blocking::ConnectionBuilder;
+
+    use super::*;
+    use crate::testing::{PrivateBus, test_state};
+
+    #[test]
+    fn matches_rules() {
+        let rule = FlashRule { app_name: "mail".to_string(), ..Default::default() };
+        assert!(rule.matches("Thunderbird Mail", "New message"));
+        assert!(!rule.matches("Chat", "New mail"));
+
+        let rule = FlashRule { summary: "Battery".to_string(), ..Default::default() };
+        assert!(rule.matches("Power", "battery low"));
+        assert!(FlashRule::default().matches("", ""));
+    }
+
+    #[test]
+    fn flashes_on_notifications_from_a_private_bus() {
+        let bus = PrivateBus::start();
+        let state = test_state("notify");
+        {
+            let mut state = state.lock().unwrap();
+            state.cfg.notifications.enabled = true;
+            state.cfg.notifications.rules.push(FlashRule { app_name: "Mail".to_string(), colors: vec![[1, 2, 3]], duration: 10, repeat: 1, ..Default::default() });
+        }
+
+        let monitor = ConnectionBuilder::address(bus.address.as_str()).unwrap().build().unwrap();
+        let messages = become_monitor(&monitor).unwrap();
+        let watched = state.clone();
+        thread::spawn(move || watch(messages, &watched));
+
+        let client = ConnectionBuilder::address(bus.address.as_str()).unwrap().build().unwrap();
+        let notify = |app_name: &str| {
+            let args: NotifyArgs = (app_name.to_string(), 0, String::new(), "Hello".to_string(), String::new(), Vec::new(), HashMap::new(), -1);
+            // Nothing shows notifications on this bus, but the call is seen all the same
+            let _ = client.call_method(Some("org.freedesktop.Notifications"), "/org/freedesktop/Notifications", Some("org.freedesktop.Notifications"), "Notify", &args);
+        };
+        notify("Chat");
+        notify("Mail");
+
+        // Three zones set to the flash color and then three restored to white
+        let written = || fs::read(std::env::temp_dir().join("predator-ng-notify-static")).unwrap();
+        let start = Instant::now();
+        while written().len() < 48 && start.elapsed() < Duration::from_secs(1) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(50));
+
+        let zones: Vec<[u8; 3]> = written().chunks(8).map(|data| [data[2], data[3], data[4]]).collect();
+        assert_eq!(zones, [[1, 2, 3], [1, 2, 3], [1, 2, 3], [255, 255, 255], [255, 255, 255], [255, 255, 255]]);
+    }
+}
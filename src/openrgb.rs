@@ -0,0 +1,901 @@
+use std::{io::{self, Read, Write}, net::{Ipv4Addr, TcpListener, TcpStream}, path::{Path, PathBuf}, sync::Mutex, thread, time::Duration};
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, KBDynamicDirection, KBDynamicEffect, KBLightMode, KBLighting, State, SharedState, apply_lighting, control, effects::Capabilities};
+
+// The OpenRGB network SDK protocol, see https://gitlab.com/CalcProgrammer1/OpenRGB/-/wikis/OpenRGB-SDK-Documentation
+
+const MAGIC: &[u8; 4] = b"ORGB";
+const HEADER_SIZE: usize = 16;
+// Newer versions add zone segments and flags, which clients only send if both sides agree on them
+const PROTOCOL_VERSION: u32 = 3;
+
+const REQUEST_CONTROLLER_COUNT: u32 = 0;
+const REQUEST_CONTROLLER_DATA: u32 = 1;
+const REQUEST_PROTOCOL_VERSION: u32 = 40;
+const SET_CLIENT_NAME: u32 = 50;
+const REQUEST_PROFILE_LIST: u32 = 150;
+const REQUEST_LOAD_PROFILE: u32 = 152;
+const UPDATE_LEDS: u32 = 1050;
+const UPDATE_ZONE_LEDS: u32 = 1051;
+const UPDATE_SINGLE_LED: u32 = 1052;
+const SET_CUSTOM_MODE: u32 = 1100;
+const UPDATE_MODE: u32 = 1101;
+const SAVE_MODE: u32 = 1102;
+
+const DEVICE_TYPE_KEYBOARD: i32 = 5;
+const ZONE_TYPE_SINGLE: i32 = 0;
+
+const MODE_FLAG_HAS_SPEED: u32 = 1 << 0;
+const MODE_FLAG_HAS_DIRECTION_LR: u32 = 1 << 1;
+const MODE_FLAG_HAS_BRIGHTNESS: u32 = 1 << 4;
+const MODE_FLAG_HAS_PER_LED_COLOR: u32 = 1 << 5;
+const MODE_FLAG_HAS_MODE_SPECIFIC_COLOR: u32 = 1 << 6;
+const MODE_FLAG_MANUAL_SAVE: u32 = 1 << 8;
+
+const MODE_COLORS_NONE: u32 = 0;
+const MODE_COLORS_PER_LED: u32 = 1;
+const MODE_COLORS_MODE_SPECIFIC: u32 = 2;
+
+const MODE_DIRECTION_LEFT: u32 = 0;
+const MODE_DIRECTION_RIGHT: u32 = 1;
+
+const DEFAULT_PORT: u16 = 6742;
+// Far more than clients send us, so a bad size can't make us allocate gigabytes
+const MAX_PACKET_SIZE: u32 = 64 * 1024;
+// Controller data from servers names every LED and can be large for long strips and big matrices
+const MAX_REPLY_SIZE: u32 = 16 * 1024 * 1024;
+
+// How often the followed device's colors are fetched
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+// How often the device list is refreshed while following, counted in fetches
+const DEVICE_LIST_REFRESH: u32 = 50;
+
+// The index and name of each device on the server being followed, for the settings to choose from
+static DEVICES: Mutex<Vec<(u32, String)>> = Mutex::new(Vec::new());
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct OpenRgbSettings {
+    pub server: bool,
+    pub port: u16,
+    /// Mirrors the colors of a device on another OpenRGB server
+    pub client: bool,
+    pub address: String,
+    pub device: String
+}
+
+impl Default for OpenRgbSettings {
+    fn default() -> Self {
+        Self {
+            server: false,
+            port: DEFAULT_PORT,
+            client: false,
+            address: format!("localhost:{}", DEFAULT_PORT),
+            device: String::new()
+        }
+    }
+}
+
+#[derive(Default)]
+struct Packet(Vec<u8>);
+
+impl Packet {
+    fn u16(&mut self, value: u16) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u16(value.len() as u16 + 1);
+        self.0.extend(value.as_bytes());
+        self.0.push(0);
+    }
+
+    fn color(&mut self, color: [u8; 3]) {
+        self.0.extend([color[0], color[1], color[2], 0]);
+    }
+
+    /// Prefixes the data with its size, which counts the size field itself
+    fn sized(self) -> Vec<u8> {
+        let mut data = ((self.0.len() + 4) as u32).to_le_bytes().to_vec();
+        data.extend(self.0);
+        data
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Option<&[u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u16()? as usize;
+        self.take(len).map(|bytes| String::from_utf8_lossy(bytes).trim_end_matches('\0').to_string())
+    }
+
+    fn color(&mut self) -> Option<[u8; 3]> {
+        self.take(4).map(|bytes| [bytes[0], bytes[1], bytes[2]])
+    }
+
+    fn colors(&mut self) -> Option<Vec<[u8; 3]>> {
+        let count = self.u16()?;
+        (0..count).map(|_| self.color()).collect()
+    }
+
+    fn skip_strings(&mut self, count: usize) -> Option<()> {
+        for _ in 0..count {
+            self.string()?;
+        }
+        Some(())
+    }
+
+    fn skip_u32s(&mut self, count: usize) -> Option<()> {
+        self.take(count * 4).map(|_| ())
+    }
+}
+
+/// The modes we describe: static colors first, then the firmware effects in `KBDynamicEffect::ALL` order
+fn active_mode(kb: &KBLighting) -> i32 {
+    match kb.mode {
+        KBLightMode::Static | KBLightMode::Script | KBLightMode::Plugin => 0,
+        KBLightMode::Dynamic => KBDynamicEffect::ALL.iter().position(|effect| *effect == kb.effect).unwrap_or(0) as i32 + 1
+    }
+}
+
+// The static mode has no capabilities, its colors are per LED
+fn write_mode(packet: &mut Packet, version: u32, kb: &KBLighting, name: &str, value: i32, capabilities: Option<Capabilities>) {
+    packet.string(name);
+    packet.i32(value);
+    match capabilities {
+        Some(capabilities) => {
+            let mut flags = MODE_FLAG_HAS_SPEED | MODE_FLAG_HAS_BRIGHTNESS | MODE_FLAG_MANUAL_SAVE;
+            if capabilities.direction {
+                flags |= MODE_FLAG_HAS_DIRECTION_LR;
+            }
+            if capabilities.color {
+                flags |= MODE_FLAG_HAS_MODE_SPECIFIC_COLOR;
+            }
+            packet.u32(flags);
+        }
+        None => packet.u32(MODE_FLAG_HAS_PER_LED_COLOR | MODE_FLAG_HAS_BRIGHTNESS | MODE_FLAG_MANUAL_SAVE)
+    }
+    let (min_speed, max_speed) = capabilities.map_or((1, 9), |capabilities| (capabilities.min_speed, capabilities.max_speed));
+    packet.u32(min_speed as u32);
+    packet.u32(max_speed as u32);
+    if version >= 3 {
+        packet.u32(0);
+        packet.u32(100);
+    }
+    let colors = capabilities.map_or(0, |capabilities| capabilities.color as u32);
+    packet.u32(colors);
+    packet.u32(colors);
+    packet.u32(kb.speed as u32);
+    if version >= 3 {
+        packet.u32(kb.brightness as u32);
+    }
+    packet.u32(if kb.direction == KBDynamicDirection::RightToLeft { MODE_DIRECTION_LEFT } else { MODE_DIRECTION_RIGHT });
+    match capabilities {
+        Some(capabilities) if capabilities.color => {
+            packet.u32(MODE_COLORS_MODE_SPECIFIC);
+            packet.u16(1);
+            packet.color(kb.color);
+        }
+        Some(_) => {
+            packet.u32(MODE_COLORS_NONE);
+            packet.u16(0);
+        }
+        None => {
+            packet.u32(MODE_COLORS_PER_LED);
+            packet.u16(0);
+        }
+    }
+}
+
+/// `capabilities` are those of the effects in `KBDynamicEffect::ALL`
+fn controller_data(version: u32, kb: &KBLighting, capabilities: &[Capabilities]) -> Vec<u8> {
+    let mut packet = Packet::default();
+    packet.i32(DEVICE_TYPE_KEYBOARD);
+    packet.string("Acer Predator Keyboard");
+    if version >= 1 {
+        packet.string("Acer");
+    }
+    packet.string("RGB keyboard controlled by predator-ng");
+    packet.string(env!("CARGO_PKG_VERSION"));
+    packet.string("");
+    packet.string("/dev/acer-gkbbl-0");
+
+    packet.u16(KBDynamicEffect::ALL.len() as u16 + 1);
+    packet.i32(active_mode(kb));
+    write_mode(&mut packet, version, kb, "Static", 0, None);
+    for (effect, capabilities) in KBDynamicEffect::ALL.into_iter().zip(capabilities) {
+        write_mode(&mut packet, version, kb, effect.name(), effect as i32, Some(*capabilities));
+    }
+
+    packet.u16(kb.zones.len() as u16);
+    for i in 0..kb.zones.len() {
+        packet.string(&format!("Zone {}", i + 1));
+        packet.i32(ZONE_TYPE_SINGLE);
+        packet.u32(1);
+        packet.u32(1);
+        packet.u32(1);
+        packet.u16(0);
+    }
+
+    packet.u16(kb.zones.len() as u16);
+    for i in 0..kb.zones.len() {
+        packet.string(&format!("Zone {}", i + 1));
+        packet.u32(i as u32);
+    }
+
+    packet.u16(kb.zones.len() as u16);
+    for zone in kb.zones {
+        packet.color(zone.color);
+    }
+
+    packet.sized()
+}
+
+/// Reads the name and LED colors out of another server's controller data
+fn parse_controller_data(version: u32, data: &[u8]) -> Option<(String, Vec<[u8; 3]>)> {
+    let mut reader = Reader(data);
+    reader.skip_u32s(2)?;
+    let name = reader.string()?;
+    reader.skip_strings(if version >= 1 { 5 } else { 4 })?;
+
+    let modes = reader.u16()?;
+    reader.u32()?;
+    for _ in 0..modes {
+        reader.string()?;
+        reader.skip_u32s(if version >= 3 { 12 } else { 9 })?;
+        reader.colors()?;
+    }
+
+    let zones = reader.u16()?;
+    for _ in 0..zones {
+        reader.string()?;
+        reader.skip_u32s(4)?;
+        let matrix_size = reader.u16()?;
+        reader.take(matrix_size as usize)?;
+    }
+
+    let leds = reader.u16()?;
+    for _ in 0..leds {
+        reader.string()?;
+        reader.u32()?;
+    }
+
+    Some((name, reader.colors()?))
+}
+
+/// Averages the LEDs of a device into one color per zone, splitting them from left to right
+fn zone_colors(leds: &[[u8; 3]], zones: usize) -> Vec<[u8; 3]> {
+    (0..zones).map(|zone| {
+        let start = zone * leds.len() / zones;
+        let end = ((zone + 1) * leds.len() / zones).max(start + 1).min(leds.len());
+        let part = &leds[start..end];
+        if part.is_empty() {
+            return [0, 0, 0];
+        }
+
+        let mut color = [0; 3];
+        for channel in 0..3 {
+            color[channel] = (part.iter().map(|led| led[channel] as usize).sum::<usize>() / part.len()) as u8;
+        }
+        color
+    }).collect()
+}
+
+fn profile_list(cfg: &Config) -> Vec<u8> {
+    let mut packet = Packet::default();
+    packet.u16(cfg.profiles.len() as u16);
+    for profile in &cfg.profiles {
+        packet.string(&profile.name);
+    }
+
+    packet.sized()
+}
+
+/// Shows `colors` on the zones starting at `first`. The zones are switched on, OpenRGB turns LEDs off by making them black.
+fn update_zones(state: &SharedState, first: usize, colors: &[[u8; 3]]) {
+    let mut state = state.lock().unwrap();
+    let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+
+    for (zone, color) in cfg.kb.zones.iter_mut().skip(first).zip(colors) {
+        zone.color = *color;
+        zone.enabled = true;
+    }
+    cfg.kb.mode = KBLightMode::Static;
+    apply_lighting(static_dev, dynamic_dev, cfg);
+}
+
+fn update_mode(state: &SharedState, version: u32, data: &[u8]) -> Option<()> {
+    let mut reader = Reader(data);
+    reader.u32()?;
+    let index = reader.u32()? as usize;
+    reader.string()?;
+    reader.u32()?;
+    reader.u32()?;
+    reader.u32()?;
+    reader.u32()?;
+    if version >= 3 {
+        reader.u32()?;
+        reader.u32()?;
+    }
+    reader.u32()?;
+    reader.u32()?;
+    let speed = reader.u32()?;
+    let brightness = if version >= 3 { Some(reader.u32()?) } else { None };
+    let direction = reader.u32()?;
+    reader.u32()?;
+    let colors = reader.colors()?;
+
+    let mut state = state.lock().unwrap();
+    let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+
+    if index == 0 {
+        cfg.kb.mode = KBLightMode::Static;
+    } else {
+        cfg.kb.mode = KBLightMode::Dynamic;
+        cfg.kb.effect = *KBDynamicEffect::ALL.get(index - 1)?;
+        let capabilities = cfg.effects.capabilities(cfg.kb.effect);
+        cfg.kb.speed = capabilities.clamp_speed(speed.min(u8::MAX as u32) as u8);
+        if capabilities.direction {
+            cfg.kb.direction = if direction == MODE_DIRECTION_LEFT { KBDynamicDirection::RightToLeft } else { KBDynamicDirection::LeftToRight };
+        }
+        if let Some(color) = colors.first().filter(|_| capabilities.color) {
+            cfg.kb.color = *color;
+        }
+    }
+    if let Some(brightness) = brightness {
+        cfg.kb.brightness = control::snap_brightness(cfg, brightness.min(100) as u8);
+    }
+    apply_lighting(static_dev, dynamic_dev, cfg);
+
+    Some(())
+}
+
+fn send(stream: &mut TcpStream, device: u32, id: u32, data: &[u8]) -> io::Result<()> {
+    let mut packet = MAGIC.to_vec();
+    packet.extend(device.to_le_bytes());
+    packet.extend(id.to_le_bytes());
+    packet.extend((data.len() as u32).to_le_bytes());
+    packet.extend(data);
+
+    stream.write_all(&packet)
+}
+
+/// Reads the next packet, returning its device, id and data.
+/// Packets larger than `max_size` are skipped with an error, so that the next one can still be read.
+fn receive(stream: &mut TcpStream, max_size: u32) -> io::Result<(u32, u32, Vec<u8>)> {
+    let mut header = [0; HEADER_SIZE];
+    stream.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not an OpenRGB packet"));
+    }
+    let field = |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+    let (device, id, size) = (field(4), field(8), field(12));
+    if size > max_size {
+        io::copy(&mut stream.take(size as u64), &mut io::sink())?;
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "OpenRGB packet too large"));
+    }
+
+    let mut data = vec![0; size as usize];
+    stream.read_exact(&mut data)?;
+
+    Ok((device, id, data))
+}
+
+/// Sends a request and waits for its reply, skipping the notifications servers send in between
+fn request(stream: &mut TcpStream, device: u32, id: u32, data: &[u8]) -> io::Result<Vec<u8>> {
+    send(stream, device, id, data)?;
+    loop {
+        let (reply_device, reply_id, reply) = receive(stream, MAX_REPLY_SIZE)?;
+        if reply_device == device && reply_id == id {
+            return Ok(reply);
+        }
+    }
+}
+
+fn handle_client(mut stream: TcpStream, state: &SharedState, config_path: &Path) -> io::Result<()> {
+    let mut version = 0;
+
+    loop {
+        let (device, id, data) = receive(&mut stream, MAX_PACKET_SIZE)?;
+        let mut reader = Reader(&data);
+
+        // There's only one device, everything addressed to other ones is ignored
+        match id {
+            REQUEST_CONTROLLER_COUNT => send(&mut stream, device, id, &1u32.to_le_bytes())?,
+            REQUEST_PROTOCOL_VERSION => {
+                version = reader.u32().unwrap_or(0).min(PROTOCOL_VERSION);
+                send(&mut stream, device, id, &PROTOCOL_VERSION.to_le_bytes())?;
+            }
+            REQUEST_CONTROLLER_DATA if device == 0 => {
+                let version = reader.u32().unwrap_or(version).min(PROTOCOL_VERSION);
+                let (kb, capabilities) = {
+                    let cfg = &state.lock().unwrap().cfg;
+                    (cfg.kb, KBDynamicEffect::ALL.map(|effect| cfg.effects.capabilities(effect)))
+                };
+                send(&mut stream, device, id, &controller_data(version, &kb, &capabilities))?;
+            }
+            REQUEST_PROFILE_LIST => {
+                let list = profile_list(&state.lock().unwrap().cfg);
+                send(&mut stream, device, id, &list)?;
+            }
+            REQUEST_LOAD_PROFILE => {
+                let name = String::from_utf8_lossy(&data).trim_end_matches('\0').to_string();
+                let state = state.clone();
+                let config_path = config_path.to_path_buf();
+                thread::spawn(move || {
+                    if control::apply_profile(&state, &name) {
+                        control::save(&state, &config_path);
+                    }
+                });
+            }
+            UPDATE_LEDS if device == 0 => {
+                reader.u32();
+                if let Some(colors) = reader.colors() {
+                    update_zones(state, 0, &colors);
+                }
+            }
+            UPDATE_ZONE_LEDS if device == 0 => {
+                reader.u32();
+                let zone = reader.u32();
+                if let (Some(zone), Some(colors)) = (zone, reader.colors()) {
+                    update_zones(state, zone as usize, &colors[..colors.len().min(1)]);
+                }
+            }
+            UPDATE_SINGLE_LED if device == 0 => {
+                let led = reader.u32();
+                if let (Some(led), Some(color)) = (led, reader.color()) {
+                    update_zones(state, led as usize, &[color]);
+                }
+            }
+            SET_CUSTOM_MODE if device == 0 => {
+                let mut state = state.lock().unwrap();
+                let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+                cfg.kb.mode = KBLightMode::Static;
+                apply_lighting(static_dev, dynamic_dev, cfg);
+            }
+            UPDATE_MODE if device == 0 => {
+                update_mode(state, version, &data);
+            }
+            // Clients stream colors many times a second, so they're only saved when asked to
+            SAVE_MODE if device == 0 => {
+                update_mode(state, version, &data);
+                control::save(state, config_path);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn serve(listener: TcpListener, state: SharedState, config_path: PathBuf) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let state = state.clone();
+                let config_path = config_path.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_client(stream, &state, &config_path) {
+                        if e.kind() != io::ErrorKind::UnexpectedEof {
+                            eprintln!("[ERROR]: OpenRGB client disconnected: {}", e);
+                        }
+                    }
+                });
+            }
+            Err(e) => eprintln!("[ERROR]: Could not accept an OpenRGB client: {}", e)
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Shows `colors` on the zones over the lighting, without changing it
+fn mirror(state: &SharedState, colors: &[[u8; 3]]) {
+    let mut state = state.lock().unwrap();
+    let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+
+    for (zone, color) in cfg.overlay.zones.iter_mut().zip(colors) {
+        *zone = Some(*color);
+    }
+    apply_lighting(static_dev, dynamic_dev, cfg);
+}
+
+/// Fetches the name and LED colors of `device`, or `None` if its data is too large or can't be parsed
+fn fetch_controller(stream: &mut TcpStream, version: u32, device: u32) -> io::Result<Option<(String, Vec<[u8; 3]>)>> {
+    match request(stream, device, REQUEST_CONTROLLER_DATA, &version.to_le_bytes()) {
+        Ok(data) => Ok(parse_controller_data(version, &data)),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Ok(None),
+        Err(e) => Err(e)
+    }
+}
+
+/// Mirrors the chosen device until following is turned off or pointed at another server
+fn follow(state: &SharedState, address: &str) -> io::Result<()> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let reply = request(&mut stream, 0, REQUEST_PROTOCOL_VERSION, &PROTOCOL_VERSION.to_le_bytes())?;
+    let version = Reader(&reply).u32().ok_or_else(|| invalid_data("Bad protocol version"))?.min(PROTOCOL_VERSION);
+    send(&mut stream, 0, SET_CLIENT_NAME, b"predator-ng\0")?;
+
+    let mut fetches = 0;
+    let mut last_colors = Vec::new();
+    loop {
+        let settings = state.lock().unwrap().cfg.openrgb.clone();
+        if !settings.client || settings.address != address {
+            return Ok(());
+        }
+
+        if fetches % DEVICE_LIST_REFRESH == 0 {
+            let reply = request(&mut stream, 0, REQUEST_CONTROLLER_COUNT, &[])?;
+            let count = Reader(&reply).u32().ok_or_else(|| invalid_data("Bad controller count"))?;
+
+            // Devices that can't be read are left out, the others can still be followed
+            let mut devices = Vec::new();
+            for device in 0..count {
+                if let Some((name, _)) = fetch_controller(&mut stream, version, device)? {
+                    devices.push((device, name));
+                }
+            }
+            *DEVICES.lock().unwrap() = devices;
+        }
+        fetches += 1;
+
+        let device = DEVICES.lock().unwrap().iter().find(|(_, name)| *name == settings.device).map(|(device, _)| *device);
+        if let Some((_, leds)) = device.map(|device| fetch_controller(&mut stream, version, device)).transpose()?.flatten() {
+            let colors = zone_colors(&leds, 3);
+            if colors != last_colors {
+                mirror(state, &colors);
+                last_colors = colors;
+            }
+        }
+
+        thread::sleep(FOLLOW_INTERVAL);
+    }
+}
+
+/// Starts the OpenRGB SDK server on localhost if it's enabled, and follows another server's device while that's enabled
+pub fn spawn(state: SharedState, config_path: PathBuf) {
+    let settings = state.lock().unwrap().cfg.openrgb.clone();
+    if settings.server {
+        match TcpListener::bind((Ipv4Addr::LOCALHOST, settings.port)) {
+            Ok(listener) => {
+                let state = state.clone();
+                thread::spawn(move || serve(listener, state, config_path));
+            }
+            Err(e) => eprintln!("[ERROR]: Could not start the OpenRGB server on port {}: {}", settings.port, e)
+        }
+    }
+
+    thread::spawn(move || {
+        loop {
+            let settings = state.lock().unwrap().cfg.openrgb.clone();
+            if settings.client {
+                if let Err(e) = follow(&state, &settings.address) {
+                    eprintln!("[ERROR]: Could not follow the OpenRGB device: {}", e);
+                }
+                DEVICES.lock().unwrap().clear();
+
+                // Following was never part of the lighting, which shows again as it is now
+                let mut state = state.lock().unwrap();
+                let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+                if cfg.mirroring() {
+                    cfg.overlay.zones = [None; 3];
+                    apply_lighting(static_dev, dynamic_dev, cfg);
+                }
+            }
+
+            thread::sleep(Duration::from_secs(5));
+        }
+    });
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config) -> bool {
+    let settings = &mut cfg.openrgb;
+
+    let mut changed = ui.checkbox(&mut settings.server, "Let OpenRGB control the keyboard")
+        .on_hover_text("Add localhost to the SDK client in OpenRGB. Takes effect after restarting predator-ng")
+        .changed();
+    ui.add_enabled_ui(settings.server, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Port");
+            changed |= ui.add(egui::DragValue::new(&mut settings.port).clamp_range(1024..=65535)).changed();
+        });
+    });
+
+    changed |= ui.checkbox(&mut settings.client, "Follow a device from OpenRGB").changed();
+    ui.add_enabled_ui(settings.client, |ui| {
+        egui::Grid::new("OpenRgbClient").num_columns(2).show(ui, |ui| {
+            ui.label("Server");
+            changed |= ui.text_edit_singleline(&mut settings.address).changed();
+            ui.end_row();
+
+            ui.label("Device");
+            egui::ComboBox::from_id_source("OpenRgbDevice")
+                .selected_text(settings.device.as_str())
+                .show_ui(ui, |ui| {
+                    for (_, name) in DEVICES.lock().unwrap().iter() {
+                        changed |= ui.selectable_value(&mut settings.device, name.clone(), name).changed();
+                    }
+                });
+            ui.end_row();
+        });
+    });
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{effects::EffectSettings, testing::{test_config_path, test_state}};
+
+    // Following keeps the device list in a static, so only one test can follow at a time
+    static FOLLOWING: Mutex<()> = Mutex::new(());
+
+    // Controller data the way OpenRGB sends it for a device without modes, with all of its LEDs in one zone
+    fn fake_controller(name: &str, leds: &[[u8; 3]]) -> Vec<u8> {
+        let mut packet = Packet::default();
+        packet.i32(DEVICE_TYPE_KEYBOARD);
+        packet.string(name);
+        for field in ["Vendor", "Description", "1.0", "Serial", "Location"] {
+            packet.string(field);
+        }
+        packet.u16(0);
+        packet.i32(0);
+
+        packet.u16(1);
+        packet.string("Strip");
+        packet.i32(ZONE_TYPE_SINGLE);
+        packet.u32(leds.len() as u32);
+        packet.u32(leds.len() as u32);
+        packet.u32(leds.len() as u32);
+        packet.u16(0);
+
+        packet.u16(leds.len() as u16);
+        for i in 0..leds.len() {
+            packet.string(&format!("LED {}", i + 1));
+            packet.u32(i as u32);
+        }
+        packet.u16(leds.len() as u16);
+        for led in leds {
+            packet.color(*led);
+        }
+
+        packet.sized()
+    }
+
+    #[test]
+    fn parses_our_own_controller_data() {
+        let mut kb = KBLighting::default();
+        kb.zones[0].color = [255, 0, 0];
+        kb.zones[1].color = [0, 255, 0];
+        kb.zones[2].color = [0, 0, 255];
+        let capabilities = KBDynamicEffect::ALL.map(|effect| EffectSettings::default().capabilities(effect));
+
+        for version in 0..=PROTOCOL_VERSION {
+            let data = controller_data(version, &kb, &capabilities);
+            let (name, leds) = parse_controller_data(version, &data).unwrap();
+            assert_eq!(name, "Acer Predator Keyboard");
+            assert_eq!(leds, vec![[255, 0, 0], [0, 255, 0], [0, 0, 255]]);
+        }
+    }
+
+    #[test]
+    fn parses_other_controller_data() {
+        let leds = [[10, 20, 30], [40, 50, 60]];
+        let data = fake_controller("Fake Strip", &leds);
+        assert_eq!(parse_controller_data(PROTOCOL_VERSION, &data), Some(("Fake Strip".to_string(), leds.to_vec())));
+
+        // Cut off in the middle of the colors
+        assert_eq!(parse_controller_data(PROTOCOL_VERSION, &data[..data.len() - 2]), None);
+    }
+
+    #[test]
+    fn averages_leds_into_zones() {
+        let leds = [[0, 0, 0], [100, 50, 10], [200, 0, 0], [0, 200, 0], [1, 2, 3], [3, 2, 1]];
+        assert_eq!(zone_colors(&leds, 3), vec![[50, 25, 5], [100, 100, 0], [2, 2, 2]]);
+
+        // Fewer LEDs than zones share them out
+        assert_eq!(zone_colors(&[[1, 1, 1], [2, 2, 2]], 3), vec![[1, 1, 1], [1, 1, 1], [2, 2, 2]]);
+        assert_eq!(zone_colors(&[], 3), vec![[0, 0, 0]; 3]);
+    }
+
+    #[test]
+    fn follows_a_device() {
+        let _following = FOLLOWING.lock().unwrap();
+        let leds = [[255, 0, 0], [255, 0, 0], [0, 0, 0], [0, 100, 0], [0, 0, 50], [0, 0, 150]];
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            while let Ok((device, id, _)) = receive(&mut stream, MAX_PACKET_SIZE) {
+                match id {
+                    REQUEST_PROTOCOL_VERSION => send(&mut stream, device, id, &PROTOCOL_VERSION.to_le_bytes()).unwrap(),
+                    REQUEST_CONTROLLER_COUNT => send(&mut stream, device, id, &1u32.to_le_bytes()).unwrap(),
+                    REQUEST_CONTROLLER_DATA => send(&mut stream, device, id, &fake_controller("Fake Strip", &leds)).unwrap(),
+                    _ => {}
+                }
+            }
+        });
+
+        let state = test_state("follow");
+        let saved_zones = {
+            let cfg = &mut state.lock().unwrap().cfg;
+            cfg.openrgb.client = true;
+            cfg.openrgb.address = address.clone();
+            cfg.openrgb.device = "Fake Strip".to_string();
+            cfg.kb.zones
+        };
+        let following = {
+            let state = state.clone();
+            thread::spawn(move || follow(&state, &address))
+        };
+
+        let started = Instant::now();
+        while !state.lock().unwrap().cfg.mirroring() {
+            assert!(started.elapsed() < Duration::from_secs(5), "Nothing was mirrored");
+            thread::sleep(Duration::from_millis(10));
+        }
+        {
+            let cfg = &mut state.lock().unwrap().cfg;
+            assert_eq!(cfg.overlay.zones, [Some([255, 0, 0]), Some([0, 50, 0]), Some([0, 0, 100])]);
+            assert!(cfg.kb.zones == saved_zones);
+            cfg.openrgb.client = false;
+        }
+
+        following.join().unwrap().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn skips_devices_it_cant_read() {
+        let _following = FOLLOWING.lock().unwrap();
+        // Every LED is named, so this is well over the size of the packets clients send us
+        let leds = vec![[0, 0, 90]; 6000];
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let server = {
+            let leds = leds.clone();
+            thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                while let Ok((device, id, _)) = receive(&mut stream, MAX_PACKET_SIZE) {
+                    match id {
+                        REQUEST_PROTOCOL_VERSION => send(&mut stream, device, id, &PROTOCOL_VERSION.to_le_bytes()).unwrap(),
+                        REQUEST_CONTROLLER_COUNT => send(&mut stream, device, id, &3u32.to_le_bytes()).unwrap(),
+                        REQUEST_CONTROLLER_DATA => match device {
+                            0 => send(&mut stream, device, id, b"garbage").unwrap(),
+                            1 => send(&mut stream, device, id, &vec![0; MAX_REPLY_SIZE as usize + 1]).unwrap(),
+                            _ => send(&mut stream, device, id, &fake_controller("Big Strip", &leds)).unwrap()
+                        },
+                        _ => {}
+                    }
+                }
+            })
+        };
+        assert!(fake_controller("Big Strip", &leds).len() > MAX_PACKET_SIZE as usize);
+
+        let state = test_state("skip");
+        {
+            let cfg = &mut state.lock().unwrap().cfg;
+            cfg.openrgb.client = true;
+            cfg.openrgb.address = address.clone();
+            cfg.openrgb.device = "Big Strip".to_string();
+        }
+        let following = {
+            let state = state.clone();
+            thread::spawn(move || follow(&state, &address))
+        };
+
+        let started = Instant::now();
+        while !state.lock().unwrap().cfg.mirroring() {
+            assert!(started.elapsed() < Duration::from_secs(5), "Nothing was mirrored");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*DEVICES.lock().unwrap(), vec![(2, "Big Strip".to_string())]);
+        {
+            let cfg = &mut state.lock().unwrap().cfg;
+            assert_eq!(cfg.overlay.zones, [Some([0, 0, 90]); 3]);
+            cfg.openrgb.client = false;
+        }
+
+        following.join().unwrap().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn serves_clients() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let state = test_state("openrgb-server");
+        {
+            let state = state.clone();
+            thread::spawn(move || serve(listener, state, test_config_path("openrgb-server")));
+        }
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let reply = request(&mut stream, 0, REQUEST_PROTOCOL_VERSION, &PROTOCOL_VERSION.to_le_bytes()).unwrap();
+        assert_eq!(Reader(&reply).u32(), Some(PROTOCOL_VERSION));
+        // Packets are handled in order, so once this is answered the ones before it are too
+        let sync = |stream: &mut TcpStream| {
+            request(stream, 0, REQUEST_CONTROLLER_COUNT, &[]).unwrap();
+            state.lock().unwrap().cfg.kb
+        };
+
+        let mut leds = Packet::default();
+        leds.u16(3);
+        for color in [[1, 1, 1], [2, 2, 2], [3, 3, 3]] {
+            leds.color(color);
+        }
+        send(&mut stream, 0, UPDATE_LEDS, &leds.sized()).unwrap();
+        let kb = sync(&mut stream);
+        assert!(kb.mode == KBLightMode::Static);
+        assert_eq!(kb.zones.map(|zone| zone.color), [[1, 1, 1], [2, 2, 2], [3, 3, 3]]);
+
+        let mut zone = Packet::default();
+        zone.u32(1);
+        zone.u16(1);
+        zone.color([20, 20, 20]);
+        send(&mut stream, 0, UPDATE_ZONE_LEDS, &zone.sized()).unwrap();
+        assert_eq!(sync(&mut stream).zones.map(|zone| zone.color), [[1, 1, 1], [20, 20, 20], [3, 3, 3]]);
+
+        let mut led = Packet::default();
+        led.u32(2);
+        led.color([30, 30, 30]);
+        send(&mut stream, 0, UPDATE_SINGLE_LED, &led.0).unwrap();
+        assert_eq!(sync(&mut stream).zones.map(|zone| zone.color), [[1, 1, 1], [20, 20, 20], [30, 30, 30]]);
+        // Other devices don't exist
+        send(&mut stream, 1, UPDATE_SINGLE_LED, &led.0).unwrap();
+
+        let wave = KBLighting { effect: KBDynamicEffect::Wave, speed: 4, brightness: 60, direction: KBDynamicDirection::RightToLeft, ..Default::default() };
+        let capabilities = EffectSettings::default().capabilities(KBDynamicEffect::Wave);
+        let index = KBDynamicEffect::ALL.iter().position(|effect| *effect == KBDynamicEffect::Wave).unwrap() as i32 + 1;
+        let mut mode = Packet::default();
+        mode.i32(index);
+        write_mode(&mut mode, PROTOCOL_VERSION, &wave, "Wave", KBDynamicEffect::Wave as i32, Some(capabilities));
+        send(&mut stream, 0, UPDATE_MODE, &mode.sized()).unwrap();
+        let kb = sync(&mut stream);
+        assert!(kb.mode == KBLightMode::Dynamic && kb.effect == KBDynamicEffect::Wave);
+        assert!(kb.direction == KBDynamicDirection::RightToLeft);
+        // The brightness moves in steps
+        assert_eq!((kb.speed, kb.brightness), (4, 50));
+
+        // Too large to be anything we understand, so the client is dropped
+        send(&mut stream, 0, UPDATE_LEDS, &vec![0; MAX_PACKET_SIZE as usize + 1]).unwrap();
+        assert!(request(&mut stream, 0, REQUEST_CONTROLLER_COUNT, &[]).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use std::{fs, path::Path};
+
+use eframe::egui;
+use predator_ng::widgets::color_box::color_box;
+use serde::{Deserialize, Serialize};
+
+const RECENT_COLORS: usize = 8;
+const GPL_HEADER: &str = "GIMP Palette";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Swatch {
+    pub name: String,
+    pub color: [u8; 3]
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub swatches: Vec<Swatch>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PaletteSettings {
+    pub palettes: Vec<Palette>,
+    /// The index of the palette shown in the panes
+    pub selected: usize,
+    /// The colors last given to the keyboard, newest first
+    pub recent: Vec<[u8; 3]>
+}
+
+impl Default for PaletteSettings {
+    fn default() -> Self {
+        Self {
+            palettes: vec![default_palette()],
+            selected: 0,
+            recent: Vec::new()
+        }
+    }
+}
+
+/// The colors that used to be the only choice
+fn default_palette() -> Palette {
+    let swatches = [
+        ("Red", [255, 0, 0]),
+        ("Orange", [255, 165, 0]),
+        ("Yellow", [255, 255, 0]),
+        ("Green", [0, 128, 0]),
+        ("Blue", [0, 0, 255]),
+        ("Indigo", [75, 0, 130]),
+        ("Violet", [148, 0, 211]),
+        ("White", [255, 255, 255])
+    ];
+
+    Palette {
+        name: "Default".to_string(),
+        swatches: swatches.into_iter().map(|(name, color)| Swatch { name: name.to_string(), color }).collect()
+    }
+}
+
+/// Reads a GIMP palette, which has a header, optional `Name:` and `Columns:` lines, `#` comments,
+/// then a line per color like `255 128 0 Orange`
+fn parse_gpl(text: &str, fallback_name: &str) -> Result<Palette, String> {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    if lines.next() != Some(GPL_HEADER) {
+        return Err("Not a GIMP palette".to_string());
+    }
+
+    let mut palette = Palette { name: fallback_name.to_string(), swatches: Vec::new() };
+    for line in lines {
+        if let Some(name) = line.strip_prefix("Name:") {
+            palette.name = name.trim().to_string();
+        } else if line.starts_with("Columns:") || line.starts_with('#') {
+            continue;
+        } else {
+            let mut parts = line.split_whitespace();
+            let mut channel = || parts.next().and_then(|channel| channel.parse::<u8>().ok());
+            let (Some(r), Some(g), Some(b)) = (channel(), channel(), channel()) else {
+                return Err(format!("Could not read the color \"{}\"", line));
+            };
+            let name = parts.collect::<Vec<_>>().join(" ");
+            palette.swatches.push(Swatch { name, color: [r, g, b] });
+        }
+    }
+
+    Ok(palette)
+}
+
+fn import_gpl(path: &str) -> Result<Palette, String> {
+    let path = Path::new(path.trim());
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let fallback_name = path.file_stem().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+    parse_gpl(&text, &fallback_name)
+}
+
+/// Puts `color` first among the recent colors. Dragging a slider only keeps the color it ends up at.
+pub fn remember(ui: &egui::Ui, settings: &mut PaletteSettings, color: [u8; 3]) {
+    let press_id = egui::Id::new("palette_recent_press");
+    let press = ui.input(|input| input.pointer.any_down().then(|| input.pointer.press_start_time()).flatten());
+    let same_press = press.is_some() && ui.data(|data| data.get_temp::<Option<f64>>(press_id)) == Some(press);
+    ui.data_mut(|data| data.insert_temp(press_id, press));
+
+    if same_press && !settings.recent.is_empty() {
+        settings.recent.remove(0);
+    }
+    settings.recent.retain(|recent| *recent != color);
+    settings.recent.insert(0, color);
+    settings.recent.truncate(RECENT_COLORS);
+}
+
+/// Lets the user edit the selected palette and the list of palettes
+fn show_editor(ui: &mut egui::Ui, id: &str, settings: &mut PaletteSettings, color: [u8; 3]) -> bool {
+    let mut changed = false;
+    let selected = settings.selected;
+
+    if let Some(palette) = settings.palettes.get_mut(selected) {
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            changed |= ui.text_edit_singleline(&mut palette.name).changed();
+        });
+
+        let mut moved = None;
+        let mut removed = None;
+        let count = palette.swatches.len();
+        egui::Grid::new(format!("{}Swatches", id)).num_columns(4).show(ui, |ui| {
+            for (i, swatch) in palette.swatches.iter_mut().enumerate() {
+                let mut preview = [0; 3];
+                ui.add(color_box(&mut preview, swatch.color));
+                changed |= ui.add(egui::TextEdit::singleline(&mut swatch.name).hint_text("Name").desired_width(100.0)).changed();
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(i > 0, egui::Button::new("Up")).clicked() {
+                        moved = Some((i, i - 1));
+                    }
+                    if ui.add_enabled(i + 1 < count, egui::Button::new("Down")).clicked() {
+                        moved = Some((i, i + 1));
+                    }
+                });
+                if ui.button("Remove").clicked() {
+                    removed = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some((from, to)) = moved {
+            palette.swatches.swap(from, to);
+            changed = true;
+        }
+        if let Some(i) = removed {
+            palette.swatches.remove(i);
+            changed = true;
+        }
+        if ui.button("Add the current color").clicked() {
+            palette.swatches.push(Swatch { name: String::new(), color });
+            changed = true;
+        }
+    }
+
+    ui.horizontal(|ui| {
+        if ui.button("New palette").clicked() {
+            settings.palettes.push(Palette { name: format!("Palette {}", settings.palettes.len() + 1), swatches: Vec::new() });
+            settings.selected = settings.palettes.len() - 1;
+            changed = true;
+        }
+        // The panes always need a palette to show
+        if ui.add_enabled(selected < settings.palettes.len() && settings.palettes.len() > 1, egui::Button::new("Delete palette")).clicked() {
+            settings.palettes.remove(selected);
+            settings.selected = selected.saturating_sub(1);
+            changed = true;
+        }
+    });
+
+    let path_id = egui::Id::new(id).with("import_path");
+    let error_id = egui::Id::new(id).with("import_error");
+    let mut path = ui.data(|data| data.get_temp::<String>(path_id)).unwrap_or_default();
+    ui.horizontal(|ui| {
+        ui.add(egui::TextEdit::singleline(&mut path).hint_text("/path/to/palette.gpl"));
+        if ui.add_enabled(!path.trim().is_empty(), egui::Button::new("Import")).clicked() {
+            match import_gpl(&path) {
+                Ok(palette) => {
+                    settings.palettes.push(palette);
+                    settings.selected = settings.palettes.len() - 1;
+                    path.clear();
+                    ui.data_mut(|data| data.remove::<String>(error_id));
+                    changed = true;
+                }
+                Err(e) => ui.data_mut(|data| data.insert_temp(error_id, e))
+            }
+        }
+    }).response.on_hover_text("GIMP, Inkscape and Krita palettes");
+    ui.data_mut(|data| data.insert_temp(path_id, path));
+    if let Some(error) = ui.data(|data| data.get_temp::<String>(error_id)) {
+        ui.colored_label(ui.visuals().error_fg_color, error);
+    }
+
+    changed
+}
+
+/// Shows the selected palette and the recent colors as swatches that set `color` when clicked.
+/// Returns whether `color` was picked and whether the palettes were changed.
+pub fn show(ui: &mut egui::Ui, id: &str, settings: &mut PaletteSettings, color: &mut [u8; 3]) -> (bool, bool) {
+    let mut picked = false;
+    let mut changed = false;
+
+    let editing_id = egui::Id::new(id).with("editing");
+    let mut editing = ui.data(|data| data.get_temp::<bool>(editing_id)).unwrap_or(false);
+
+    ui.horizontal_wrapped(|ui| {
+        let selected_name = settings.palettes.get(settings.selected).map_or("", |palette| palette.name.as_str());
+        egui::ComboBox::from_id_source(format!("{}Palettes", id))
+            .selected_text(selected_name)
+            .show_ui(ui, |ui| {
+                for (i, palette) in settings.palettes.iter().enumerate() {
+                    changed |= ui.selectable_value(&mut settings.selected, i, &palette.name).changed();
+                }
+            });
+        if let Some(palette) = settings.palettes.get(settings.selected) {
+            for swatch in &palette.swatches {
+                let response = ui.add(color_box(color, swatch.color));
+                picked |= response.changed();
+                if !swatch.name.is_empty() {
+                    response.on_hover_text(&swatch.name);
+                }
+            }
+        }
+        ui.toggle_value(&mut editing, "Edit");
+    });
+    if !settings.recent.is_empty() {
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Recent");
+            for recent in &settings.recent {
+                picked |= ui.add(color_box(color, *recent)).changed();
+            }
+        });
+    }
+
+    if editing {
+        ui.group(|ui| changed |= show_editor(ui, id, settings, *color));
+    }
+    ui.data_mut(|data| data.insert_temp(editing_id, editing));
+
+    (picked, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The way GIMP saves palettes, names are separated from the channels by a tab
+    const SAMPLE: &str = "GIMP Palette
+Name: Forest Walk
+Columns: 4
+#
+# Exported from GIMP
+#
+ 34 139  34\tForest Green
+107 142  35\tOlive Drab
+ 47  79  79\tDark Slate Gray
+255 255 255
+";
+
+    #[test]
+    fn parses_gimp_palettes() {
+        let palette = parse_gpl(SAMPLE, "forest").unwrap();
+        assert_eq!(palette.name, "Forest Walk");
+
+        let swatches: Vec<(&str, [u8; 3])> = palette.swatches.iter().map(|swatch| (swatch.name.as_str(), swatch.color)).collect();
+        assert_eq!(swatches, [
+            ("Forest Green", [34, 139, 34]),
+            ("Olive Drab", [107, 142, 35]),
+            ("Dark Slate Gray", [47, 79, 79]),
+            ("", [255, 255, 255])
+        ]);
+    }
+
+    #[test]
+    fn names_unnamed_palettes_after_the_file() {
+        let palette = parse_gpl("GIMP Palette\n0 0 0 Black\n", "blacks").unwrap();
+        assert_eq!(palette.name, "blacks");
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(parse_gpl("Not a palette\n0 0 0 Black\n", "").is_err());
+        assert!(parse_gpl("GIMP Palette\n0 0 256 Too Blue\n", "").is_err());
+        assert!(parse_gpl("GIMP Palette\n0 0 Black\n", "").is_err());
+    }
+
+    #[test]
+    fn default_palette_matches_its_gimp_palette() {
+        let gpl = "GIMP Palette
+Name: Default
+Columns: 8
+# The colors that used to be the only choice
+255   0   0 Red
+255 165   0 Orange
+255 255   0 Yellow
+  0 128   0 Green
+  0   0 255 Blue
+ 75   0 130 Indigo
+148   0 211 Violet
+255 255 255 White
+";
+        assert!(parse_gpl(gpl, "") == Ok(default_palette()));
+        assert!(PaletteSettings::default().palettes == [default_palette()]);
+    }
+}
@@ -0,0 +1,357 @@
+use std::{ffi::c_void, fs, io::{self, BufRead, BufReader, Write}, os::unix::fs::PermissionsExt, path::{Path, PathBuf}, process::{Child, ChildStdin, Command, Stdio}, sync::{Mutex, atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, RecvTimeoutError}}, thread, time::{Duration, Instant}};
+
+use eframe::egui;
+use libloading::Library;
+use predator_ng_plugin::{ABI_VERSION, Frame, FrameRequest, LightingSource, ZONES, ZoneColors};
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, KBLightMode, State, SharedState, Zone, apply_lighting};
+
+const LIBRARY_EXTENSION: &str = "so";
+// An executable that takes longer than this for a frame is considered stuck and stopped
+const FRAME_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Set when something else wrote to the keyboard, so the plugin's colors have to be shown again
+static REDRAW: AtomicBool = AtomicBool::new(false);
+// Why the current plugin couldn't run, for the settings to show
+static ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PluginSettings {
+    /// The file name of the plugin in the plugins directory
+    pub plugin: String,
+    /// How often the plugin is asked for a frame, in milliseconds
+    pub interval: u64
+}
+
+impl Default for PluginSettings {
+    fn default() -> Self {
+        Self {
+            plugin: String::new(),
+            interval: 100
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/predator-ng/plugins`, next to the config file
+pub fn plugins_dir(config_path: &Path) -> PathBuf {
+    config_path.with_file_name("plugins")
+}
+
+fn is_library(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == LIBRARY_EXTENSION)
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+fn list_plugins(dir: &Path) -> Vec<String> {
+    let mut plugins: Vec<String> = fs::read_dir(dir).into_iter().flatten().flatten()
+        .map(|entry| entry.path())
+        .filter(|path| is_library(path) || is_executable(path))
+        .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+        .collect();
+    plugins.sort();
+    plugins
+}
+
+/// Asks the running plugin to show its colors again after something else wrote to the keyboard
+pub fn redraw() {
+    REDRAW.store(true, Ordering::Relaxed);
+}
+
+/// A source exported from a dynamic library with `predator_ng_plugin::export_source!`
+struct LibrarySource {
+    source: *mut c_void,
+    frame: unsafe extern "C" fn(*mut c_void, u64, *mut u8),
+    free: unsafe extern "C" fn(*mut c_void),
+    // Has to outlive the functions above, fields are dropped after `drop` runs
+    _library: Library
+}
+
+impl LibrarySource {
+    fn load(path: &Path) -> Result<Self, String> {
+        // Loading runs the library's initializers, plugins are trusted like any other program the user runs
+        unsafe {
+            let library = Library::new(path).map_err(|e| e.to_string())?;
+            let abi_version = *library.get::<extern "C" fn() -> u32>(b"predator_ng_abi_version").map_err(|e| e.to_string())?;
+            if abi_version() != ABI_VERSION {
+                return Err(format!("The plugin was built for version {} of the plugin API instead of {}", abi_version(), ABI_VERSION));
+            }
+
+            let new = *library.get::<extern "C" fn() -> *mut c_void>(b"predator_ng_source_new").map_err(|e| e.to_string())?;
+            let frame = *library.get::<unsafe extern "C" fn(*mut c_void, u64, *mut u8)>(b"predator_ng_source_frame").map_err(|e| e.to_string())?;
+            let free = *library.get::<unsafe extern "C" fn(*mut c_void)>(b"predator_ng_source_free").map_err(|e| e.to_string())?;
+
+            Ok(Self { source: new(), frame, free, _library: library })
+        }
+    }
+}
+
+impl LightingSource for LibrarySource {
+    fn frame(&mut self, elapsed: Duration) -> ZoneColors {
+        let mut colors = [0; ZONES * 3];
+        unsafe { (self.frame)(self.source, elapsed.as_millis() as u64, colors.as_mut_ptr()) };
+
+        let mut zones = [[0; 3]; ZONES];
+        for (zone, color) in zones.iter_mut().zip(colors.chunks(3)) {
+            zone.copy_from_slice(color);
+        }
+        zones
+    }
+}
+
+impl Drop for LibrarySource {
+    fn drop(&mut self) {
+        unsafe { (self.free)(self.source) };
+    }
+}
+
+/// An executable exchanging a line of JSON per frame over its stdin and stdout
+struct ProcessSource {
+    child: Child,
+    stdin: ChildStdin,
+    // Read on a thread of their own, so a plugin that stops answering can't hold up the keyboard
+    lines: Receiver<io::Result<String>>
+}
+
+impl ProcessSource {
+    fn spawn(path: &Path) -> io::Result<Self> {
+        let mut child = Command::new(path).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+
+        // Ends once the process is killed and its stdout closes
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                if tx.send(line).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Self { child, stdin, lines })
+    }
+
+    /// Fails if the plugin doesn't answer within `FRAME_TIMEOUT`, after which it should be dropped to stop it
+    fn frame(&mut self, elapsed: Duration) -> io::Result<ZoneColors> {
+        let request = FrameRequest { elapsed_ms: elapsed.as_millis() as u64 };
+        writeln!(self.stdin, "{}", serde_json::to_string(&request)?)?;
+
+        let line = match self.lines.recv_timeout(FRAME_TIMEOUT) {
+            Ok(line) => line?,
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, format!("The plugin didn't answer within {} seconds", FRAME_TIMEOUT.as_secs())));
+            }
+            Err(RecvTimeoutError::Disconnected) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The plugin exited"))
+        };
+        let frame: Frame = serde_json::from_str(&line)?;
+
+        Ok(frame.zones)
+    }
+}
+
+impl Drop for ProcessSource {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+enum Source {
+    Library(LibrarySource),
+    Process(ProcessSource)
+}
+
+impl Source {
+    fn load(path: &Path) -> Result<Self, String> {
+        if is_library(path) {
+            LibrarySource::load(path).map(Source::Library)
+        } else {
+            ProcessSource::spawn(path).map(Source::Process).map_err(|e| e.to_string())
+        }
+    }
+
+    fn frame(&mut self, elapsed: Duration) -> Result<ZoneColors, String> {
+        match self {
+            Source::Library(source) => Ok(source.frame(elapsed)),
+            Source::Process(source) => source.frame(elapsed).map_err(|e| e.to_string())
+        }
+    }
+}
+
+struct Loaded {
+    path: PathBuf,
+    // None when the plugin failed, so it's only tried again once another one is chosen
+    source: Option<Source>,
+    started: Instant
+}
+
+fn report(error: Option<String>) {
+    if let Some(error) = &error {
+        eprintln!("[ERROR]: Plugin failed: {}", error);
+    }
+    *ERROR.lock().unwrap() = error;
+}
+
+/// Runs the chosen plugin while the plugin mode is selected
+pub fn spawn(state: SharedState, config_path: PathBuf) {
+    let dir = plugins_dir(&config_path);
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("[ERROR]: Could not create {}: {}", dir.display(), e);
+    }
+
+    thread::spawn(move || {
+        let mut loaded: Option<Loaded> = None;
+        let mut shown: Option<ZoneColors> = None;
+
+        loop {
+            let settings = {
+                let state = state.lock().unwrap();
+                (state.cfg.kb.mode == KBLightMode::Plugin).then(|| state.cfg.plugins.clone())
+            };
+            let Some(settings) = settings.filter(|settings| !settings.plugin.is_empty()) else {
+                loaded = None;
+                shown = None;
+                thread::sleep(Duration::from_millis(PluginSettings::default().interval));
+                continue;
+            };
+
+            let path = dir.join(&settings.plugin);
+            if loaded.as_ref().is_none_or(|loaded| loaded.path != path) {
+                // Drop the old plugin first, so a process doesn't keep running alongside its replacement
+                drop(loaded.take());
+                let source = Source::load(&path);
+                report(source.as_ref().err().cloned());
+                loaded = Some(Loaded { path, source: source.ok(), started: Instant::now() });
+                shown = None;
+            }
+
+            let Loaded { source, started, .. } = loaded.as_mut().unwrap();
+            let zones = source.as_mut().map(|source| source.frame(started.elapsed()));
+            match zones {
+                Some(Ok(zones)) => {
+                    let mut state = state.lock().unwrap();
+                    let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+
+                    if cfg.kb.mode == KBLightMode::Plugin && (shown != Some(zones) || REDRAW.swap(false, Ordering::Relaxed)) {
+                        let mut plugin_cfg = cfg.clone();
+                        plugin_cfg.kb.mode = KBLightMode::Static;
+                        plugin_cfg.kb.zones = zones.map(|color| Zone { color, enabled: true });
+                        apply_lighting(static_dev, dynamic_dev, &plugin_cfg);
+                        shown = Some(zones);
+                    }
+                }
+                // Dropping the source kills a process that's stuck
+                Some(Err(e)) => {
+                    report(Some(e));
+                    *source = None;
+                }
+                None => {}
+            }
+
+            thread::sleep(Duration::from_millis(settings.interval.max(10)));
+        }
+    });
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config, config_path: &Path) -> bool {
+    let settings = &mut cfg.plugins;
+    let dir = plugins_dir(config_path);
+    let mut changed = false;
+
+    ui.horizontal(|ui| {
+        ui.label("Plugin");
+        egui::ComboBox::from_id_source("Plugin")
+            .selected_text(settings.plugin.as_str())
+            .show_ui(ui, |ui| {
+                for plugin in list_plugins(&dir) {
+                    changed |= ui.selectable_value(&mut settings.plugin, plugin.clone(), plugin).changed();
+                }
+            });
+    }).response.on_hover_text(format!("Libraries and executables in {}", dir.display()));
+    ui.horizontal(|ui| {
+        ui.label("Frame every");
+        changed |= ui.add(egui::DragValue::new(&mut settings.interval).clamp_range(10..=10000).suffix(" ms")).changed();
+    });
+    if let Some(error) = ERROR.lock().unwrap().as_ref() {
+        ui.colored_label(ui.visuals().error_fg_color, error);
+    }
+    ui.add_space(10.0);
+    ui.label("Executables get a line like {\"elapsed_ms\": 100} on stdin for every frame and answer with the zone colors:");
+    ui.label(egui::RichText::new("{\"zones\": [[255, 0, 0], [0, 255, 0], [0, 0, 255]]}").code());
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    fn script(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("predator-ng-{}", name));
+        fs::write(&path, contents).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    // Another test forking while the script is still open for writing makes it busy for a moment
+    fn spawn(path: &Path) -> ProcessSource {
+        loop {
+            match ProcessSource::spawn(path) {
+                Err(e) if e.raw_os_error() == Some(libc::ETXTBSY) => thread::sleep(Duration::from_millis(10)),
+                source => return source.unwrap()
+            }
+        }
+    }
+
+    #[test]
+    fn runs_executables() {
+        let path = script("answers", "#!/bin/sh\nwhile read request; do echo '{\"zones\": [[1, 2, 3], [4, 5, 6], [7, 8, 9]]}'; done\n");
+        let mut source = spawn(&path);
+        assert_eq!(source.frame(Duration::ZERO).unwrap(), [[1, 2, 3], [4, 5, 6], [7, 8, 9]]);
+        assert_eq!(source.frame(Duration::from_secs(1)).unwrap(), [[1, 2, 3], [4, 5, 6], [7, 8, 9]]);
+
+        let path = script("exits", "#!/bin/sh\nexit 0\n");
+        assert!(spawn(&path).frame(Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn stops_executables_that_hang() {
+        let path = script("hangs", "#!/bin/sh\nread request\nsleep 60\n");
+        let mut source = spawn(&path);
+        let pid = source.child.id();
+
+        let started = Instant::now();
+        let error = source.frame(Duration::ZERO).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() >= FRAME_TIMEOUT && started.elapsed() < FRAME_TIMEOUT * 2);
+
+        drop(source);
+        assert!(!Path::new(&format!("/proc/{}", pid)).exists());
+    }
+
+    #[test]
+    fn loads_libraries() {
+        // Built into a directory of its own, as the one the tests run from is locked while they run
+        let target_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("plugin-fixtures");
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--quiet", "-p", "predator-ng-plugin", "--example", "rotate", "--target-dir"])
+            .arg(&target_dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let path = target_dir.join("debug").join("examples").join(format!("librotate.{}", LIBRARY_EXTENSION));
+        let mut source = LibrarySource::load(&path).unwrap();
+        assert_eq!(source.frame(Duration::ZERO), [[255, 0, 0], [0, 255, 0], [0, 0, 255]]);
+        assert_eq!(source.frame(Duration::from_millis(1500)), [[0, 0, 255], [255, 0, 0], [0, 255, 0]]);
+
+        assert!(LibrarySource::load(&path.with_file_name("missing.so")).is_err());
+    }
+}
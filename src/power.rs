@@ -0,0 +1,166 @@
+use std::{fs::{self, File}, io::{self, Read}, mem, os::fd::{FromRawFd, OwnedFd}, sync::mpsc::{self, RecvTimeoutError, Sender}, thread, time::Duration};
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, SharedState, action::{self, Action}};
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+// How often the rules are checked for being turned on. The power supplies are only read then and on uevents,
+// or every time if uevents can't be listened to, as sysfs attributes don't emit inotify events.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(2);
+// The multicast group of the uevents coming straight from the kernel, which udev listens to as well
+const UEVENT_KERNEL_GROUP: u32 = 1;
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PowerRules {
+    pub enabled: bool,
+    pub on_ac: Action,
+    pub on_battery: Action
+}
+
+/// Returns whether any mains power supply is online, or `None` if there are no mains supplies
+pub fn ac_online() -> Option<bool> {
+    let mut found = false;
+
+    for entry in fs::read_dir(POWER_SUPPLY_DIR).ok()?.flatten() {
+        let path = entry.path();
+        let kind = fs::read_to_string(path.join("type")).unwrap_or_default();
+        if kind.trim() != "Mains" {
+            continue;
+        }
+
+        found = true;
+        if fs::read_to_string(path.join("online")).unwrap_or_default().trim() == "1" {
+            return Some(true);
+        }
+    }
+
+    found.then_some(false)
+}
+
+// Uevents are `action@devpath` followed by `KEY=value` fields, all ending in a null byte
+fn is_power_supply_event(message: &[u8]) -> bool {
+    message.split(|byte| *byte == 0).any(|field| field == b"SUBSYSTEM=power_supply")
+}
+
+/// Sends on `changes` whenever the kernel reports a change to a power supply
+fn watch_uevents(changes: Sender<()>) -> io::Result<()> {
+    let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, libc::NETLINK_KOBJECT_UEVENT) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    address.nl_groups = UEVENT_KERNEL_GROUP;
+    let bound = unsafe {
+        libc::bind(fd, &address as *const libc::sockaddr_nl as *const libc::sockaddr, mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t)
+    };
+    if bound < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut socket = File::from(socket);
+    thread::spawn(move || {
+        let mut message = [0; 8192];
+        loop {
+            let power_supply_changed = match socket.read(&mut message) {
+                Ok(len) => is_power_supply_event(&message[..len]),
+                // The socket's buffer overflowed, so a power supply event may have been dropped
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => false,
+                Err(e) => {
+                    eprintln!("[ERROR]: Stopped listening for power supply changes, polling instead: {}", e);
+                    return;
+                }
+            };
+            if power_supply_changed && changes.send(()).is_err() {
+                return;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+pub fn spawn(state: SharedState) {
+    let (changes_tx, changes) = mpsc::channel();
+    let mut watching = match watch_uevents(changes_tx) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("[ERROR]: Could not listen for power supply changes, polling instead: {}", e);
+            false
+        }
+    };
+
+    thread::spawn(move || {
+        let mut last_online = None;
+        let mut changed = false;
+
+        loop {
+            let rules = state.lock().unwrap().cfg.power.clone();
+
+            if !rules.enabled {
+                // Forget the last state so that enabling the rules applies them right away
+                last_online = None;
+            } else if changed || last_online.is_none() || !watching {
+                let online = ac_online();
+                if online != last_online {
+                    if let Some(on_ac) = online {
+                        action::apply(&state, if on_ac { &rules.on_ac } else { &rules.on_battery });
+                    }
+                    last_online = online;
+                }
+            }
+
+            if !watching {
+                thread::sleep(RECHECK_INTERVAL);
+                continue;
+            }
+            changed = match changes.recv_timeout(RECHECK_INTERVAL) {
+                Ok(()) => true,
+                Err(RecvTimeoutError::Timeout) => false,
+                Err(RecvTimeoutError::Disconnected) => {
+                    watching = false;
+                    false
+                }
+            };
+            // A plug or unplug comes with several events
+            while changes.try_recv().is_ok() {}
+        }
+    });
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config) -> bool {
+    let choices = action::platform_profile_choices();
+    let step = cfg.fade.step();
+    let Config { profiles, power, .. } = cfg;
+
+    let mut changed = ui.checkbox(&mut power.enabled, "Switch automatically when AC is plugged or unplugged").changed();
+    ui.add_enabled_ui(power.enabled, |ui| {
+        ui.label("On AC");
+        changed |= action::show(ui, "PowerAC", profiles, &choices, step, &mut power.on_ac);
+        ui.add_space(5.0);
+        ui.label("On Battery");
+        changed |= action::show(ui, "PowerBattery", profiles, &choices, step, &mut power.on_battery);
+    });
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_power_supply_events() {
+        let ac = b"change@/devices/LNXSYSTM:00/ACPI0003:00/power_supply/ADP1\0ACTION=change\0SUBSYSTEM=power_supply\0POWER_SUPPLY_ONLINE=1\0";
+        let usb = b"add@/devices/pci0000:00/usb1/1-1\0ACTION=add\0SUBSYSTEM=usb\0DEVTYPE=usb_device\0";
+        assert!(is_power_supply_event(ac));
+        assert!(!is_power_supply_event(usb));
+    }
+}
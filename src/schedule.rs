@@ -0,0 +1,276 @@
+use std::{f64::consts::PI, thread, time::Duration};
+
+use chrono::{Datelike, Local, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, SharedState, action::{self, Action}};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum TimePoint {
+    Clock { hour: u8, minute: u8 },
+    Sunrise,
+    Sunset
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ScheduleEntry {
+    pub name: String,
+    pub start: TimePoint,
+    pub end: TimePoint,
+    pub action: Action
+}
+
+impl Default for ScheduleEntry {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            start: TimePoint::Clock { hour: 9, minute: 0 },
+            end: TimePoint::Clock { hour: 18, minute: 0 },
+            action: Default::default()
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Schedule {
+    pub enabled: bool,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub entries: Vec<ScheduleEntry>
+}
+
+/// Calculates sunrise and sunset in minutes after UTC midnight using the NOAA approximation.
+/// Returns `None` during polar day or night.
+fn sun_minutes(date: NaiveDate, latitude: f64, longitude: f64) -> Option<(f64, f64)> {
+    let gamma = 2.0 * PI / 365.0 * date.ordinal0() as f64;
+    let eqtime = 229.18 * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin()
+        - 0.014615 * (2.0 * gamma).cos() - 0.040849 * (2.0 * gamma).sin());
+    let decl = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos() + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos() + 0.00148 * (3.0 * gamma).sin();
+
+    let lat = latitude.to_radians();
+    let cos_ha = 90.833_f64.to_radians().cos() / (lat.cos() * decl.cos()) - lat.tan() * decl.tan();
+    if !(-1.0..=1.0).contains(&cos_ha) {
+        return None;
+    }
+    let ha = cos_ha.acos().to_degrees();
+
+    let sunrise = 720.0 - 4.0 * (longitude + ha) - eqtime;
+    let sunset = 720.0 - 4.0 * (longitude - ha) - eqtime;
+
+    Some((sunrise, sunset))
+}
+
+/// The local sunrise and sunset times, `None` during polar day or night
+pub fn sun_times(date: NaiveDate, latitude: f64, longitude: f64) -> Option<(NaiveTime, NaiveTime)> {
+    let (sunrise, sunset) = sun_minutes(date, latitude, longitude)?;
+
+    let to_local = |minutes: f64| {
+        let midnight = Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?);
+        let time = midnight + chrono::Duration::seconds((minutes * 60.0) as i64);
+        Some(time.with_timezone(&Local).time())
+    };
+
+    Some((to_local(sunrise)?, to_local(sunset)?))
+}
+
+fn minutes(time: NaiveTime) -> u32 {
+    time.hour() * 60 + time.minute()
+}
+
+fn resolve(point: TimePoint, sun: Option<(NaiveTime, NaiveTime)>) -> Option<u32> {
+    match point {
+        TimePoint::Clock { hour, minute } => Some(hour as u32 * 60 + minute as u32),
+        TimePoint::Sunrise => sun.map(|(sunrise, _)| minutes(sunrise)),
+        TimePoint::Sunset => sun.map(|(_, sunset)| minutes(sunset))
+    }
+}
+
+fn contains(start: u32, end: u32, now: u32) -> bool {
+    if start <= end {
+        (start..end).contains(&now)
+    } else {
+        // The range wraps around midnight
+        now >= start || now < end
+    }
+}
+
+/// Returns the index of the first entry whose time range contains the current time
+pub fn active_entry(schedule: &Schedule) -> Option<usize> {
+    let now = Local::now();
+    let sun = sun_times(now.date_naive(), schedule.latitude, schedule.longitude);
+    let now = minutes(now.time());
+
+    schedule.entries.iter().position(|entry| {
+        match (resolve(entry.start, sun), resolve(entry.end, sun)) {
+            (Some(start), Some(end)) => contains(start, end, now),
+            _ => false
+        }
+    })
+}
+
+pub fn spawn(state: SharedState) {
+    thread::spawn(move || {
+        let mut last_active = None;
+
+        loop {
+            let schedule = state.lock().unwrap().cfg.schedule.clone();
+
+            if !schedule.enabled {
+                last_active = None;
+            } else {
+                let active = active_entry(&schedule);
+                if active != last_active {
+                    if let Some(i) = active {
+                        action::apply(&state, &schedule.entries[i].action);
+                    }
+                    last_active = active;
+                }
+            }
+
+            thread::sleep(CHECK_INTERVAL);
+        }
+    });
+}
+
+fn time_point_picker(ui: &mut egui::Ui, id: String, point: &mut TimePoint) -> bool {
+    let mut changed = false;
+
+    ui.horizontal(|ui| {
+        let text = match point {
+            TimePoint::Clock { .. } => "Time",
+            TimePoint::Sunrise => "Sunrise",
+            TimePoint::Sunset => "Sunset"
+        };
+        egui::ComboBox::from_id_source(id)
+            .selected_text(text)
+            .width(80.0)
+            .show_ui(ui, |ui| {
+                if ui.selectable_label(matches!(point, TimePoint::Clock { .. }), "Time").clicked() && !matches!(point, TimePoint::Clock { .. }) {
+                    *point = TimePoint::Clock { hour: 0, minute: 0 };
+                    changed = true;
+                }
+                changed |= ui.selectable_value(point, TimePoint::Sunrise, "Sunrise").changed();
+                changed |= ui.selectable_value(point, TimePoint::Sunset, "Sunset").changed();
+            });
+        if let TimePoint::Clock { hour, minute } = point {
+            changed |= ui.add(egui::DragValue::new(hour).clamp_range(0..=23)).changed();
+            ui.label(":");
+            changed |= ui.add(egui::DragValue::new(minute).clamp_range(0..=59)).changed();
+        }
+    });
+
+    changed
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config) -> bool {
+    let choices = action::platform_profile_choices();
+    let step = cfg.fade.step();
+    let Config { profiles, schedule, .. } = cfg;
+
+    let mut changed = ui.checkbox(&mut schedule.enabled, "Change the lighting based on the time of day").changed();
+    ui.add_enabled_ui(schedule.enabled, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Latitude");
+            changed |= ui.add(egui::DragValue::new(&mut schedule.latitude).clamp_range(-90.0..=90.0).speed(0.1)).changed();
+            ui.label("Longitude");
+            changed |= ui.add(egui::DragValue::new(&mut schedule.longitude).clamp_range(-180.0..=180.0).speed(0.1)).changed();
+        });
+        match sun_times(Local::now().date_naive(), schedule.latitude, schedule.longitude) {
+            Some((sunrise, sunset)) => ui.label(format!("Sunrise today at {}, sunset at {}", sunrise.format("%H:%M"), sunset.format("%H:%M"))),
+            None => ui.label("The sun doesn't rise or set today at this location")
+        };
+
+        let mut removed = None;
+        for (i, entry) in schedule.entries.iter_mut().enumerate() {
+            ui.separator();
+            ui.horizontal(|ui| {
+                changed |= ui.add(egui::TextEdit::singleline(&mut entry.name).hint_text("Name").desired_width(100.0)).changed();
+                changed |= time_point_picker(ui, format!("ScheduleStart{}", i), &mut entry.start);
+                ui.label("to");
+                changed |= time_point_picker(ui, format!("ScheduleEnd{}", i), &mut entry.end);
+                if ui.button("Remove").clicked() {
+                    removed = Some(i);
+                }
+            });
+            changed |= action::show(ui, &format!("ScheduleAction{}", i), profiles, &choices, step, &mut entry.action);
+        }
+        if let Some(i) = removed {
+            schedule.entries.remove(i);
+            changed = true;
+        }
+
+        ui.separator();
+        if ui.button("Add time range").clicked() {
+            schedule.entries.push(Default::default());
+            changed = true;
+        }
+    });
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(minutes: f64, hour: u32, minute: u32) {
+        let expected = (hour * 60 + minute) as f64;
+        assert!((minutes - expected).abs() <= 5.0, "{} minutes instead of about {}", minutes, expected);
+    }
+
+    #[test]
+    fn finds_the_sun() {
+        // Greenwich at midsummer
+        let (sunrise, sunset) = sun_minutes(NaiveDate::from_ymd_opt(2023, 6, 21).unwrap(), 51.48, 0.0).unwrap();
+        assert_near(sunrise, 3, 43);
+        assert_near(sunset, 20, 21);
+
+        // New York in winter, west of Greenwich
+        let (sunrise, sunset) = sun_minutes(NaiveDate::from_ymd_opt(2023, 12, 21).unwrap(), 40.71, -74.01).unwrap();
+        assert_near(sunrise, 12, 16);
+        assert_near(sunset, 21, 32);
+    }
+
+    #[test]
+    fn has_no_sunrise_in_polar_day_or_night() {
+        assert!(sun_times(NaiveDate::from_ymd_opt(2023, 6, 21).unwrap(), 69.65, 18.96).is_none());
+        assert!(sun_times(NaiveDate::from_ymd_opt(2023, 12, 21).unwrap(), 69.65, 18.96).is_none());
+        assert!(sun_times(NaiveDate::from_ymd_opt(2023, 3, 20).unwrap(), 69.65, 18.96).is_some());
+    }
+
+    #[test]
+    fn resolves_time_points() {
+        let sun = Some((NaiveTime::from_hms_opt(6, 30, 0).unwrap(), NaiveTime::from_hms_opt(19, 45, 0).unwrap()));
+        assert_eq!(resolve(TimePoint::Clock { hour: 22, minute: 15 }, None), Some(1335));
+        assert_eq!(resolve(TimePoint::Sunrise, sun), Some(390));
+        assert_eq!(resolve(TimePoint::Sunset, sun), Some(1185));
+        assert_eq!(resolve(TimePoint::Sunset, None), None);
+    }
+
+    #[test]
+    fn contains_times_in_the_range() {
+        // 09:00 to 18:00, the end isn't included
+        assert!(contains(540, 1080, 540));
+        assert!(contains(540, 1080, 1079));
+        assert!(!contains(540, 1080, 1080));
+        assert!(!contains(540, 1080, 0));
+
+        // 22:00 to 06:00 wraps around midnight
+        assert!(contains(1320, 360, 1320));
+        assert!(contains(1320, 360, 1439));
+        assert!(contains(1320, 360, 0));
+        assert!(contains(1320, 360, 359));
+        assert!(!contains(1320, 360, 360));
+        assert!(!contains(1320, 360, 720));
+
+        // An empty range never applies
+        assert!(!contains(600, 600, 600));
+    }
+}
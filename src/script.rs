@@ -0,0 +1,368 @@
+use std::{cell::RefCell, fs, path::{Path, PathBuf}, rc::Rc, sync::{Mutex, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, Instant, SystemTime}};
+
+use eframe::egui;
+use rhai::{AST, Engine, EvalAltResult, Scope};
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, KBDynamicEffect, KBLightMode, KBLighting, State, SharedState, apply_lighting, effects::EffectSettings, power};
+
+const EXTENSION: &str = "rhai";
+// Keeps a script stuck in a loop from taking the keyboard down with it
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+// Set when something else wrote to the keyboard, so the script's lighting has to be shown again
+static REDRAW: AtomicBool = AtomicBool::new(false);
+// Why the current script couldn't run, for the settings to show
+static ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ScriptSettings {
+    /// The file name of the script in the scripts directory
+    pub script: String,
+    /// How often the script's `tick` function is called, in milliseconds
+    pub interval: u64
+}
+
+impl Default for ScriptSettings {
+    fn default() -> Self {
+        Self {
+            script: String::new(),
+            interval: 100
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/predator-ng/scripts`, next to the config file
+pub fn scripts_dir(config_path: &Path) -> PathBuf {
+    config_path.with_file_name("scripts")
+}
+
+fn list_scripts(dir: &Path) -> Vec<String> {
+    let mut scripts: Vec<String> = fs::read_dir(dir).into_iter().flatten().flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == EXTENSION))
+        .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+/// Asks the running script to show its lighting again after something else wrote to the keyboard
+pub fn redraw() {
+    REDRAW.store(true, Ordering::Relaxed);
+}
+
+fn read_number(path: &str) -> Option<f64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// The hottest thermal zone in degrees Celsius
+fn cpu_temp() -> f64 {
+    fs::read_dir("/sys/class/thermal").into_iter().flatten().flatten()
+        .filter_map(|zone| read_number(&format!("{}/temp", zone.path().display())))
+        .fold(0.0, f64::max) / 1000.0
+}
+
+/// The total and idle time from the first line of /proc/stat
+fn cpu_times() -> Option<(u64, u64)> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    let times: Vec<u64> = stat.lines().next()?.split_whitespace().skip(1).filter_map(|time| time.parse().ok()).collect();
+
+    Some((times.iter().sum(), times.get(3)? + times.get(4).unwrap_or(&0)))
+}
+
+fn memory_usage() -> f64 {
+    let Ok(meminfo) = fs::read_to_string("/proc/meminfo") else {
+        return 0.0;
+    };
+    let field = |name: &str| meminfo.lines()
+        .find(|line| line.starts_with(name))
+        .and_then(|line| line.split_whitespace().nth(1)?.parse::<f64>().ok())
+        .unwrap_or(0.0);
+
+    let total = field("MemTotal:");
+    if total == 0.0 { 0.0 } else { 100.0 * (total - field("MemAvailable:")) / total }
+}
+
+/// The charge of the first battery, or -1 without one
+fn battery() -> i64 {
+    fs::read_dir("/sys/class/power_supply").into_iter().flatten().flatten()
+        .find(|supply| fs::read_to_string(supply.path().join("type")).is_ok_and(|kind| kind.trim() == "Battery"))
+        .and_then(|supply| read_number(&format!("{}/capacity", supply.path().display())))
+        .map_or(-1, |capacity| capacity as i64)
+}
+
+fn to_channel(value: i64) -> u8 {
+    value.clamp(0, 255) as u8
+}
+
+/// Makes the functions scripts can call. They change `kb`, which is shown after every tick.
+/// Speeds are kept in the range `effects` gives the effect.
+fn engine(kb: &Rc<RefCell<KBLighting>>, effects: &Rc<RefCell<EffectSettings>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+
+    let lighting = kb.clone();
+    engine.register_fn("set_zone", move |zone: i64, r: i64, g: i64, b: i64| -> Result<(), Box<EvalAltResult>> {
+        let mut kb = lighting.borrow_mut();
+        let zones = kb.zones.len();
+        let zone = usize::try_from(zone - 1).ok().and_then(|index| kb.zones.get_mut(index))
+            .ok_or_else(|| format!("There's no zone {}, they go from 1 to {}", zone, zones))?;
+        zone.color = [to_channel(r), to_channel(g), to_channel(b)];
+        zone.enabled = true;
+        kb.mode = KBLightMode::Static;
+
+        Ok(())
+    });
+    let lighting = kb.clone();
+    engine.register_fn("set_brightness", move |level: i64| lighting.borrow_mut().brightness = level.clamp(0, 100) as u8);
+    let (lighting, capabilities) = (kb.clone(), effects.clone());
+    engine.register_fn("set_effect", move |name: &str| {
+        let mut kb = lighting.borrow_mut();
+        if let Some(effect) = KBDynamicEffect::ALL.into_iter().find(|effect| effect.name().eq_ignore_ascii_case(name)) {
+            kb.effect = effect;
+            kb.speed = capabilities.borrow().capabilities(effect).clamp_speed(kb.speed);
+            kb.mode = KBLightMode::Dynamic;
+        }
+    });
+    let (lighting, capabilities) = (kb.clone(), effects.clone());
+    engine.register_fn("set_speed", move |speed: i64| {
+        let mut kb = lighting.borrow_mut();
+        kb.speed = capabilities.borrow().capabilities(kb.effect).clamp_speed(speed.clamp(0, u8::MAX as i64) as u8);
+    });
+    let lighting = kb.clone();
+    engine.register_fn("set_color", move |r: i64, g: i64, b: i64| lighting.borrow_mut().color = [to_channel(r), to_channel(g), to_channel(b)]);
+
+    engine.register_fn("cpu_temp", cpu_temp);
+    let last_cpu_times = RefCell::new(cpu_times());
+    engine.register_fn("cpu_usage", move || {
+        let now = cpu_times();
+        let usage = match (*last_cpu_times.borrow(), now) {
+            (Some((total_before, idle_before)), Some((total, idle))) if total > total_before => {
+                100.0 * (1.0 - (idle - idle_before) as f64 / (total - total_before) as f64)
+            }
+            _ => 0.0
+        };
+        *last_cpu_times.borrow_mut() = now;
+        usage
+    });
+    engine.register_fn("memory_usage", memory_usage);
+    engine.register_fn("battery", battery);
+    engine.register_fn("on_ac", || power::ac_online().unwrap_or(true));
+
+    engine
+}
+
+struct Loaded {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    // None when the script failed to compile, so it's only tried again once it changes
+    script: Option<(AST, Scope<'static>)>,
+    started: Instant
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn load(engine: &Engine, path: &Path) -> Result<(AST, Scope<'static>), Box<EvalAltResult>> {
+    let ast = engine.compile_file(path.to_path_buf())?;
+    let mut scope = Scope::new();
+    engine.run_ast_with_scope(&mut scope, &ast)?;
+
+    Ok((ast, scope))
+}
+
+fn report(error: Option<String>) {
+    if let Some(error) = &error {
+        eprintln!("[ERROR]: Script failed: {}", error);
+    }
+    *ERROR.lock().unwrap() = error;
+}
+
+/// Runs the chosen script while the script mode is selected
+pub fn spawn(state: SharedState, config_path: PathBuf) {
+    let dir = scripts_dir(&config_path);
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("[ERROR]: Could not create {}: {}", dir.display(), e);
+    }
+
+    thread::spawn(move || {
+        let kb = Rc::new(RefCell::new(KBLighting::default()));
+        let effects = Rc::new(RefCell::new(EffectSettings::default()));
+        let engine = engine(&kb, &effects);
+        let mut loaded: Option<Loaded> = None;
+        let mut shown: Option<KBLighting> = None;
+        // What the script set the brightness to, only shown until the brightness changes from outside
+        let mut brightness: Option<u8> = None;
+        let mut outside_brightness = None;
+
+        loop {
+            let (settings, current) = {
+                let state = state.lock().unwrap();
+                *effects.borrow_mut() = state.cfg.effects.clone();
+                (state.cfg.script.clone(), state.cfg.kb)
+            };
+            let interval = Duration::from_millis(settings.interval.max(10));
+            if current.mode != KBLightMode::Script || settings.script.is_empty() {
+                loaded = None;
+                shown = None;
+                thread::sleep(interval);
+                continue;
+            }
+
+            let path = dir.join(&settings.script);
+            if loaded.as_ref().is_none_or(|loaded| loaded.path != path || loaded.modified != modified(&path)) {
+                *kb.borrow_mut() = KBLighting { mode: KBLightMode::Static, ..current };
+                let script = load(&engine, &path);
+                report(script.as_ref().err().map(|e| e.to_string()));
+                loaded = Some(Loaded { modified: modified(&path), path, script: script.ok(), started: Instant::now() });
+                shown = None;
+                brightness = None;
+            }
+
+            // The brightness stays under the control of everything else, unless the script changes it
+            if outside_brightness != Some(current.brightness) {
+                brightness = None;
+                outside_brightness = Some(current.brightness);
+            }
+            let ticked_brightness = brightness.unwrap_or(current.brightness);
+            kb.borrow_mut().brightness = ticked_brightness;
+            if let Some(Loaded { script: Some((ast, scope)), started, .. }) = &mut loaded {
+                let elapsed = started.elapsed().as_millis() as i64;
+                match engine.call_fn::<()>(scope, ast, "tick", (elapsed,)) {
+                    Ok(()) => {}
+                    Err(e) if matches!(*e, EvalAltResult::ErrorFunctionNotFound(..)) => {}
+                    Err(e) => {
+                        report(Some(e.to_string()));
+                        loaded.as_mut().unwrap().script = None;
+                    }
+                }
+            }
+
+            let lighting = *kb.borrow();
+            if lighting.brightness != ticked_brightness {
+                brightness = Some(lighting.brightness);
+            }
+            {
+                let mut state = state.lock().unwrap();
+                let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+
+                // The script's lighting is only shown, the lighting that's saved keeps the script mode
+                if cfg.kb.mode == KBLightMode::Script && (shown != Some(lighting) || REDRAW.swap(false, Ordering::Relaxed)) {
+                    let mut script_cfg = cfg.clone();
+                    script_cfg.kb = lighting;
+                    apply_lighting(static_dev, dynamic_dev, &script_cfg);
+                    shown = Some(lighting);
+                }
+            }
+
+            thread::sleep(interval);
+        }
+    });
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config, config_path: &Path) -> bool {
+    let settings = &mut cfg.script;
+    let dir = scripts_dir(config_path);
+    let mut changed = false;
+
+    ui.horizontal(|ui| {
+        ui.label("Script");
+        egui::ComboBox::from_id_source("Script")
+            .selected_text(settings.script.as_str())
+            .show_ui(ui, |ui| {
+                for script in list_scripts(&dir) {
+                    changed |= ui.selectable_value(&mut settings.script, script.clone(), script).changed();
+                }
+            });
+    }).response.on_hover_text(format!("Rhai scripts in {}", dir.display()));
+    ui.horizontal(|ui| {
+        ui.label("Tick every");
+        changed |= ui.add(egui::DragValue::new(&mut settings.interval).clamp_range(10..=10000).suffix(" ms")).changed();
+    });
+    if let Some(error) = ERROR.lock().unwrap().as_ref() {
+        ui.colored_label(ui.visuals().error_fg_color, error);
+    }
+    ui.add_space(10.0);
+    ui.label("Scripts can define tick(ms), which is called with the milliseconds since the script started, and call:");
+    ui.label(egui::RichText::new("set_zone(zone, r, g, b)  set_brightness(level)  set_effect(name)  set_speed(speed)  set_color(r, g, b)").code());
+    ui.label(egui::RichText::new("cpu_temp()  cpu_usage()  memory_usage()  battery()  on_ac()").code());
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::{Capabilities, Override};
+
+    fn test_engine() -> (Engine, Rc<RefCell<KBLighting>>, Rc<RefCell<EffectSettings>>) {
+        let kb = Rc::new(RefCell::new(KBLighting::default()));
+        let effects = Rc::new(RefCell::new(EffectSettings::default()));
+        (engine(&kb, &effects), kb, effects)
+    }
+
+    #[test]
+    fn sets_zones() {
+        let (engine, kb, _) = test_engine();
+        kb.borrow_mut().mode = KBLightMode::Dynamic;
+        kb.borrow_mut().zones[1].enabled = false;
+
+        engine.run("set_zone(2, 300, -5, 128);").unwrap();
+        let zone = kb.borrow().zones[1];
+        assert_eq!((zone.color, zone.enabled), ([255, 0, 128], true));
+        assert!(kb.borrow().mode == KBLightMode::Static);
+
+        let error = engine.run("set_zone(4, 1, 2, 3);").unwrap_err().to_string();
+        assert!(error.contains("There's no zone 4"), "{}", error);
+        assert!(engine.run("set_zone(0, 1, 2, 3);").is_err());
+    }
+
+    #[test]
+    fn sets_effects() {
+        let (engine, kb, effects) = test_engine();
+        let capabilities = Capabilities { color: true, direction: true, min_speed: 2, max_speed: 5 };
+        effects.borrow_mut().overrides.push(Override { model: String::new(), effect: KBDynamicEffect::Wave, capabilities });
+        kb.borrow_mut().speed = 9;
+
+        engine.run(r#"set_effect("wave"); set_brightness(150); set_color(10, 20, 999);"#).unwrap();
+        let lighting = *kb.borrow();
+        assert!(lighting.mode == KBLightMode::Dynamic && lighting.effect == KBDynamicEffect::Wave);
+        assert_eq!((lighting.speed, lighting.brightness, lighting.color), (5, 100, [10, 20, 255]));
+
+        engine.run("set_speed(1);").unwrap();
+        assert_eq!(kb.borrow().speed, 2);
+        engine.run("set_speed(4);").unwrap();
+        assert_eq!(kb.borrow().speed, 4);
+
+        // Unknown effects are ignored
+        engine.run(r#"set_effect("Disco");"#).unwrap();
+        assert!(kb.borrow().effect == KBDynamicEffect::Wave);
+    }
+
+    #[test]
+    fn reads_the_system() {
+        let (engine, ..) = test_engine();
+        assert!(engine.eval::<f64>("memory_usage()").unwrap() >= 0.0);
+        assert!(engine.eval::<f64>("cpu_usage()").unwrap() >= 0.0);
+        assert!(engine.eval::<f64>("cpu_temp()").unwrap() >= 0.0);
+        assert!(engine.eval::<i64>("battery()").unwrap() >= -1);
+        engine.eval::<bool>("on_ac()").unwrap();
+    }
+
+    #[test]
+    fn ticks_scripts() {
+        let (engine, kb, _) = test_engine();
+        let path = std::env::temp_dir().join("predator-ng-tick.rhai");
+        fs::write(&path, "fn tick(ms) { set_brightness(ms / 1000 * 25); }\n").unwrap();
+
+        let (ast, mut scope) = load(&engine, &path).unwrap();
+        engine.call_fn::<()>(&mut scope, &ast, "tick", (2000i64,)).unwrap();
+        assert_eq!(kb.borrow().brightness, 50);
+
+        // Scripts stuck in a loop are stopped
+        assert!(engine.run("loop {}").is_err());
+    }
+}
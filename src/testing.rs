@@ -0,0 +1,46 @@
+// Helpers shared by the tests of the modules that control the keyboard
+
+use std::{fs::File, io::{BufRead, BufReader}, path::PathBuf, process::{Child, Command, Stdio}, sync::{Arc, Mutex}};
+
+use crate::{Config, State, SharedState};
+
+/// A state with files standing in for the devices, named after the test
+pub fn test_state(name: &str) -> SharedState {
+    let dir = std::env::temp_dir();
+    let static_dev = File::create(dir.join(format!("predator-ng-{}-static", name))).unwrap();
+    let dynamic_dev = File::create(dir.join(format!("predator-ng-{}-dynamic", name))).unwrap();
+    Arc::new(Mutex::new(State { cfg: Config::default(), static_dev, dynamic_dev, saved: None }))
+}
+
+/// Where a test stores its config
+pub fn test_config_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("predator-ng-{}.ron", name))
+}
+
+/// A dbus-daemon of a test's own, stopped when it's dropped
+pub struct PrivateBus {
+    daemon: Child,
+    pub address: String
+}
+
+impl PrivateBus {
+    pub fn start() -> Self {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("The D-Bus tests need dbus-daemon");
+
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.as_mut().unwrap()).read_line(&mut address).unwrap();
+
+        Self { daemon, address: address.trim().to_string() }
+    }
+}
+
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
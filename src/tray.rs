@@ -0,0 +1,572 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, process, sync::{Arc, Condvar, Mutex, atomic::{AtomicU32, Ordering}}, thread};
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use zbus::{SignalContext, blocking::{Connection, ConnectionBuilder, fdo::DBusProxy}, dbus_interface, zvariant::{ObjectPath, OwnedValue, StructureBuilder, Type, Value}};
+
+use crate::{Config, KBDynamicEffect, KBLightMode, SharedState, control, fade};
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+
+// Set by "Show Window" for the main thread, which waits for it while the window is closed
+static SHOW_WINDOW: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TraySettings {
+    pub enabled: bool
+}
+
+#[derive(Clone)]
+enum MenuAction {
+    Toggle,
+    Brightness(u8),
+    Mode(KBLightMode),
+    Effect(KBDynamicEffect),
+    Profile(String),
+    ShowWindow,
+    Quit
+}
+
+// The `(ia{sv}av)` structure dbusmenu uses, where each child is a variant holding another layout
+#[derive(Serialize, Type)]
+struct Layout {
+    id: i32,
+    properties: HashMap<String, OwnedValue>,
+    children: Vec<OwnedValue>
+}
+
+impl From<Layout> for OwnedValue {
+    fn from(layout: Layout) -> Self {
+        let structure = StructureBuilder::new()
+            .add_field(layout.id)
+            .add_field(layout.properties)
+            .add_field(layout.children)
+            .build();
+
+        Value::from(structure).into()
+    }
+}
+
+#[derive(Default)]
+struct MenuItem {
+    id: i32,
+    label: String,
+    action: Option<MenuAction>,
+    // "checkmark" or "radio" and whether it's checked
+    toggle: Option<(&'static str, bool)>,
+    separator: bool,
+    children: Vec<MenuItem>
+}
+
+impl MenuItem {
+    fn find(&self, id: i32) -> Option<&MenuItem> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(id))
+    }
+
+    fn properties(&self) -> HashMap<String, OwnedValue> {
+        let mut props = HashMap::new();
+
+        if self.separator {
+            props.insert("type".to_string(), Value::from("separator").into());
+        } else {
+            props.insert("label".to_string(), Value::from(self.label.as_str()).into());
+        }
+        if let Some((toggle_type, checked)) = self.toggle {
+            props.insert("toggle-type".to_string(), Value::from(toggle_type).into());
+            props.insert("toggle-state".to_string(), Value::from(checked as i32).into());
+        }
+        if !self.children.is_empty() {
+            props.insert("children-display".to_string(), Value::from("submenu").into());
+        }
+
+        props
+    }
+
+    /// The item's layout, `depth` levels deep or entirely if negative
+    fn layout(&self, depth: i32) -> Layout {
+        let children = if depth == 0 {
+            Vec::new()
+        } else {
+            self.children.iter().map(|child| child.layout(depth - 1).into()).collect()
+        };
+
+        Layout { id: self.id, properties: self.properties(), children }
+    }
+}
+
+/// Builds the menu from the current state. Ids are assigned in order so that they stay stable
+/// as long as the profiles don't change.
+fn build_menu(cfg: &Config) -> MenuItem {
+    let mut next_id = 0;
+    let mut item = |label: &str, action: Option<MenuAction>, toggle: Option<(&'static str, bool)>| {
+        next_id += 1;
+        MenuItem { id: next_id, label: label.to_string(), action, toggle, ..Default::default() }
+    };
+    let kb = &cfg.kb;
+
+    let mut root = MenuItem::default();
+    root.children.push(item("Keyboard Backlight", Some(MenuAction::Toggle), Some(("checkmark", kb.brightness > 0))));
+
+    let mut brightness = item("Brightness", None, None);
+    for level in (0..=100).step_by(fade::BRIGHTNESS_STEP as usize) {
+        brightness.children.push(item(&format!("{}%", level), Some(MenuAction::Brightness(level as u8)), Some(("radio", kb.brightness == level as u8))));
+    }
+    root.children.push(brightness);
+
+    let mut mode = item("Mode", None, None);
+    for m in KBLightMode::ALL {
+        mode.children.push(item(m.name(), Some(MenuAction::Mode(m)), Some(("radio", kb.mode == m))));
+    }
+    root.children.push(mode);
+
+    let mut effect = item("Effect", None, None);
+    for e in KBDynamicEffect::ALL {
+        let checked = kb.mode == KBLightMode::Dynamic && kb.effect == e;
+        effect.children.push(item(e.name(), Some(MenuAction::Effect(e)), Some(("radio", checked))));
+    }
+    root.children.push(effect);
+
+    let mut profiles = item("Profile", None, None);
+    for profile in &cfg.profiles {
+        profiles.children.push(item(&profile.name, Some(MenuAction::Profile(profile.name.clone())), None));
+    }
+    if !profiles.children.is_empty() {
+        root.children.push(profiles);
+    }
+
+    root.children.push(MenuItem { separator: true, ..item("", None, None) });
+    root.children.push(item("Show Window", Some(MenuAction::ShowWindow), None));
+    root.children.push(item("Quit", Some(MenuAction::Quit), None));
+
+    root
+}
+
+fn perform(state: &SharedState, config_path: &Path, action: MenuAction) {
+    match action {
+        MenuAction::Toggle => control::toggle_backlight(state),
+        MenuAction::Brightness(level) => control::set_brightness(state, level),
+        MenuAction::Mode(mode) => control::set_mode(state, mode),
+        MenuAction::Effect(effect) => control::set_effect(state, effect),
+        MenuAction::Profile(name) => {
+            control::apply_profile(state, &name);
+        }
+        MenuAction::ShowWindow => {
+            let (show, requested) = &SHOW_WINDOW;
+            *show.lock().unwrap() = true;
+            requested.notify_one();
+            return;
+        }
+        MenuAction::Quit => {
+            state.lock().unwrap().revert_preview();
+            process::exit(0)
+        }
+    }
+
+    control::save(state, config_path);
+}
+
+struct StatusNotifierItem {
+    state: SharedState,
+    config_path: PathBuf
+}
+
+#[dbus_interface(name = "org.kde.StatusNotifierItem")]
+impl StatusNotifierItem {
+    fn activate(&self, _x: i32, _y: i32) {
+        let state = self.state.clone();
+        let config_path = self.config_path.clone();
+        thread::spawn(move || perform(&state, &config_path, MenuAction::Toggle));
+    }
+
+    fn secondary_activate(&self, _x: i32, _y: i32) {}
+
+    fn context_menu(&self, _x: i32, _y: i32) {}
+
+    fn scroll(&self, delta: i32, orientation: &str) {
+        if orientation.to_lowercase() != "vertical" || delta == 0 {
+            return;
+        }
+
+        let state = self.state.clone();
+        let config_path = self.config_path.clone();
+        thread::spawn(move || {
+            control::step_brightness(&state, delta > 0);
+            control::save(&state, &config_path);
+        });
+    }
+
+    #[dbus_interface(property)]
+    fn category(&self) -> &str {
+        "Hardware"
+    }
+
+    #[dbus_interface(property)]
+    fn id(&self) -> &str {
+        "predator-ng"
+    }
+
+    #[dbus_interface(property)]
+    fn title(&self) -> &str {
+        "Predator-ng"
+    }
+
+    #[dbus_interface(property)]
+    fn status(&self) -> &str {
+        "Active"
+    }
+
+    #[dbus_interface(property)]
+    fn icon_name(&self) -> &str {
+        "input-keyboard"
+    }
+
+    #[dbus_interface(property)]
+    fn item_is_menu(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn menu(&self) -> ObjectPath<'_> {
+        ObjectPath::from_static_str_unchecked(MENU_PATH)
+    }
+}
+
+struct DBusMenu {
+    state: SharedState,
+    config_path: PathBuf,
+    revision: Arc<AtomicU32>
+}
+
+impl DBusMenu {
+    fn handle_event(&self, conn: &zbus::Connection, id: i32, event_id: &str) -> bool {
+        let action = {
+            let state = self.state.lock().unwrap();
+            match build_menu(&state.cfg).find(id) {
+                Some(item) => item.action.clone(),
+                None => return false
+            }
+        };
+
+        if let (Some(action), "clicked") = (action, event_id) {
+            let state = self.state.clone();
+            let config_path = self.config_path.clone();
+            let revision = self.revision.clone();
+            let conn = conn.clone();
+            thread::spawn(move || {
+                perform(&state, &config_path, action);
+
+                let revision = revision.fetch_add(1, Ordering::SeqCst) + 1;
+                if let Ok(ctxt) = SignalContext::new(&conn, MENU_PATH) {
+                    let _ = zbus::block_on(DBusMenu::layout_updated(&ctxt, revision, 0));
+                }
+            });
+        }
+
+        true
+    }
+}
+
+#[dbus_interface(name = "com.canonical.dbusmenu")]
+impl DBusMenu {
+    #[dbus_interface(out_args("revision", "layout"))]
+    fn get_layout(&self, parent_id: i32, recursion_depth: i32, _property_names: Vec<String>) -> zbus::fdo::Result<(u32, Layout)> {
+        let menu = build_menu(&self.state.lock().unwrap().cfg);
+        let parent = menu.find(parent_id)
+            .ok_or_else(|| zbus::fdo::Error::InvalidArgs(format!("No menu item with id {}", parent_id)))?;
+
+        Ok((self.revision.load(Ordering::SeqCst), parent.layout(recursion_depth)))
+    }
+
+    fn get_group_properties(&self, ids: Vec<i32>, _property_names: Vec<String>) -> Vec<(i32, HashMap<String, OwnedValue>)> {
+        let menu = build_menu(&self.state.lock().unwrap().cfg);
+
+        ids.into_iter()
+            .filter_map(|id| menu.find(id).map(|item| (id, item.properties())))
+            .collect()
+    }
+
+    fn get_property(&self, id: i32, name: &str) -> zbus::fdo::Result<OwnedValue> {
+        let menu = build_menu(&self.state.lock().unwrap().cfg);
+
+        menu.find(id)
+            .and_then(|item| item.properties().remove(name))
+            .ok_or_else(|| zbus::fdo::Error::InvalidArgs(format!("No property {} on menu item {}", name, id)))
+    }
+
+    fn event(&self, #[zbus(connection)] conn: &zbus::Connection, id: i32, event_id: &str, _data: OwnedValue, _timestamp: u32) {
+        self.handle_event(conn, id, event_id);
+    }
+
+    fn event_group(&self, #[zbus(connection)] conn: &zbus::Connection, events: Vec<(i32, String, OwnedValue, u32)>) -> Vec<i32> {
+        events.into_iter()
+            .filter(|(id, event_id, _, _)| !self.handle_event(conn, *id, event_id))
+            .map(|(id, ..)| id)
+            .collect()
+    }
+
+    fn about_to_show(&self, _id: i32) -> bool {
+        // The lighting may have been changed from elsewhere, so always refresh
+        true
+    }
+
+    #[dbus_interface(out_args("updates_needed", "id_errors"))]
+    fn about_to_show_group(&self, ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        (ids, Vec::new())
+    }
+
+    #[dbus_interface(signal)]
+    async fn layout_updated(ctxt: &SignalContext<'_>, revision: u32, parent: i32) -> zbus::Result<()>;
+
+    #[dbus_interface(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[dbus_interface(property)]
+    fn text_direction(&self) -> &str {
+        "ltr"
+    }
+
+    #[dbus_interface(property)]
+    fn status(&self) -> &str {
+        "normal"
+    }
+
+    #[dbus_interface(property)]
+    fn icon_theme_path(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+fn register(conn: &Connection, name: &str) -> zbus::Result<()> {
+    conn.call_method(
+        Some(WATCHER_NAME),
+        "/StatusNotifierWatcher",
+        Some(WATCHER_NAME),
+        "RegisterStatusNotifierItem",
+        &name
+    )?;
+
+    Ok(())
+}
+
+/// Serves the item and its menu on the bus `builder` connects to, returning the connection and the item's name
+fn serve(builder: ConnectionBuilder<'_>, state: SharedState, config_path: PathBuf) -> zbus::Result<(Connection, String)> {
+    let name = format!("org.kde.StatusNotifierItem-{}-1", process::id());
+    let item = StatusNotifierItem { state: state.clone(), config_path: config_path.clone() };
+    let menu = DBusMenu { state, config_path, revision: Default::default() };
+
+    let conn = builder
+        .name(name.as_str())?
+        .serve_at(ITEM_PATH, item)?
+        .serve_at(MENU_PATH, menu)?
+        .build()?;
+
+    Ok((conn, name))
+}
+
+/// Registers the item with the tray, and again whenever the tray (re)starts
+fn keep_registered(conn: &Connection, name: &str) -> zbus::Result<()> {
+    let dbus = DBusProxy::new(conn)?;
+    let owner_changes = dbus.receive_name_owner_changed_with_args(&[(0, WATCHER_NAME)])?;
+
+    if let Err(e) = register(conn, name) {
+        eprintln!("[ERROR]: Could not register the tray icon, waiting for a tray to appear: {}", e);
+    }
+
+    for signal in owner_changes {
+        if signal.args().is_ok_and(|args| args.new_owner().is_some()) {
+            if let Err(e) = register(conn, name) {
+                eprintln!("[ERROR]: Could not register the tray icon: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Blocks until "Show Window" is chosen, ignoring the times it was chosen while the window was already open
+pub fn wait_for_show() {
+    let (show, requested) = &SHOW_WINDOW;
+    let mut show = show.lock().unwrap();
+    *show = false;
+    while !*show {
+        show = requested.wait(show).unwrap();
+    }
+}
+
+/// Shows the tray icon if it's enabled. Returns whether the tray icon is running,
+/// in which case closing the window should keep predator-ng running.
+/// `DBUS_SESSION_BUS_ADDRESS` can point this at a private bus.
+pub fn spawn(state: SharedState, config_path: PathBuf) -> bool {
+    if !state.lock().unwrap().cfg.tray.enabled {
+        return false;
+    }
+
+    match ConnectionBuilder::session().and_then(|builder| serve(builder, state, config_path)) {
+        Ok((conn, name)) => {
+            thread::spawn(move || {
+                if let Err(e) = keep_registered(&conn, &name) {
+                    eprintln!("[ERROR]: Could not watch for the tray: {}", e);
+                }
+                // The connection has to be kept alive for the objects to be served
+                loop {
+                    thread::park();
+                }
+            });
+            true
+        }
+        Err(e) => {
+            eprintln!("[ERROR]: Could not show the tray icon: {}", e);
+            false
+        }
+    }
+}
+
+pub fn show_settings(ui: &mut egui::Ui, cfg: &mut Config) -> bool {
+    ui.checkbox(&mut cfg.tray.enabled, "Show a tray icon and keep running when the window is closed")
+        .on_hover_text("Takes effect after restarting predator-ng")
+        .changed()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use zbus::{blocking::Proxy, zvariant::Structure};
+
+    use super::*;
+    use crate::{KBLighting, Profile, testing::{PrivateBus, test_config_path, test_state}};
+
+    // What GetLayout returns: the revision and the `(ia{sv}av)` layout
+    type LayoutReply = (u32, (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>));
+
+    fn labels(item: &MenuItem) -> Vec<&str> {
+        item.children.iter().map(|child| child.label.as_str()).collect()
+    }
+
+    fn checked(item: &MenuItem) -> Vec<&str> {
+        item.children.iter()
+            .filter(|child| child.toggle.is_some_and(|(_, checked)| checked))
+            .map(|child| child.label.as_str())
+            .collect()
+    }
+
+    /// Waits up to a second for `done`, as menu actions run on their own thread
+    fn wait_for(mut done: impl FnMut() -> bool) -> bool {
+        let start = Instant::now();
+        while !done() {
+            if start.elapsed() > Duration::from_secs(1) {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    struct Watcher {
+        registered: Arc<Mutex<Vec<String>>>
+    }
+
+    #[dbus_interface(name = "org.kde.StatusNotifierWatcher")]
+    impl Watcher {
+        fn register_status_notifier_item(&self, service: &str) {
+            self.registered.lock().unwrap().push(service.to_string());
+        }
+    }
+
+    fn start_watcher(bus: &PrivateBus, registered: &Arc<Mutex<Vec<String>>>) -> Connection {
+        ConnectionBuilder::address(bus.address.as_str()).unwrap()
+            .name(WATCHER_NAME).unwrap()
+            .serve_at("/StatusNotifierWatcher", Watcher { registered: registered.clone() }).unwrap()
+            .build().unwrap()
+    }
+
+    #[test]
+    fn lays_out_the_menu() {
+        let mut cfg = Config::default();
+        cfg.kb.brightness = 50;
+        let menu = build_menu(&cfg);
+
+        assert_eq!(labels(&menu), ["Keyboard Backlight", "Brightness", "Mode", "Effect", "", "Show Window", "Quit"]);
+        assert!(menu.children[4].separator);
+        assert_eq!(labels(&menu.children[1]), ["0%", "25%", "50%", "75%", "100%"]);
+        assert_eq!(checked(&menu), ["Keyboard Backlight"]);
+        assert_eq!(checked(&menu.children[1]), ["50%"]);
+        assert_eq!(checked(&menu.children[2]), [KBLightMode::Static.name()]);
+        // The effect only applies in the dynamic mode
+        assert!(checked(&menu.children[3]).is_empty());
+
+        cfg.profiles.push(Profile { name: "Gaming".to_string(), kb: KBLighting::default() });
+        let menu = build_menu(&cfg);
+        assert_eq!(labels(&menu)[4], "Profile");
+        assert_eq!(labels(&menu.children[4]), ["Gaming"]);
+
+        // Ids are unique and found wherever they are in the tree
+        let quit = menu.children.last().unwrap();
+        assert!(matches!(menu.find(quit.id).unwrap().action, Some(MenuAction::Quit)));
+        let gaming = menu.children[4].children[0].id;
+        assert_eq!(menu.find(gaming).unwrap().label, "Gaming");
+        assert!(menu.find(quit.id + 1).is_none());
+
+        let props = menu.children[1].properties();
+        assert_eq!(props["label"], Value::from("Brightness").into());
+        assert_eq!(props["children-display"], Value::from("submenu").into());
+        let props = menu.children[1].children[0].properties();
+        assert_eq!(props["toggle-type"], Value::from("radio").into());
+        assert_eq!(props["toggle-state"], Value::from(0).into());
+        assert_eq!(menu.children[5].properties()["type"], Value::from("separator").into());
+
+        assert!(menu.layout(0).children.is_empty());
+        assert_eq!(menu.layout(1).children.len(), menu.children.len());
+    }
+
+    #[test]
+    fn registers_with_the_watcher_and_serves_the_menu() {
+        let bus = PrivateBus::start();
+        let state = test_state("tray");
+        let (service, name) = serve(ConnectionBuilder::address(bus.address.as_str()).unwrap(), state.clone(), test_config_path("tray")).unwrap();
+
+        let registered = Arc::new(Mutex::new(Vec::new()));
+        let watcher = start_watcher(&bus, &registered);
+        thread::spawn(move || keep_registered(&service, &name));
+        assert!(wait_for(|| registered.lock().unwrap().len() == 1));
+
+        // A tray that starts later gets the item too
+        drop(watcher);
+        let _watcher = start_watcher(&bus, &registered);
+        assert!(wait_for(|| registered.lock().unwrap().len() == 2));
+        let name = registered.lock().unwrap()[0].clone();
+
+        let conn = ConnectionBuilder::address(bus.address.as_str()).unwrap().build().unwrap();
+        let item = Proxy::new(&conn, name.as_str(), ITEM_PATH, "org.kde.StatusNotifierItem").unwrap();
+        assert_eq!(item.get_property::<ObjectPath>("Menu").unwrap().as_str(), MENU_PATH);
+
+        let menu = Proxy::new(&conn, name.as_str(), MENU_PATH, "com.canonical.dbusmenu").unwrap();
+        let (_, (id, _, children)): LayoutReply = menu
+            .call("GetLayout", &(0, -1, Vec::<String>::new()))
+            .unwrap();
+        assert_eq!((id, children.len()), (0, 7));
+        let brightness = Structure::try_from(children[1].clone()).unwrap();
+        assert_eq!(brightness.fields()[0], Value::from(2));
+        let Value::Array(levels) = &brightness.fields()[2] else { panic!("The children aren't an array") };
+        assert_eq!(levels.len(), 5);
+
+        let label: OwnedValue = menu.call("GetProperty", &(1, "label")).unwrap();
+        assert_eq!(label, Value::from("Keyboard Backlight").into());
+        assert!(menu.call::<_, _, OwnedValue>("GetProperty", &(1000, "label")).is_err());
+
+        let dynamic = build_menu(&Config::default()).children[2].children.iter()
+            .find(|item| matches!(item.action, Some(MenuAction::Mode(KBLightMode::Dynamic))))
+            .unwrap()
+            .id;
+        menu.call_method("Event", &(dynamic, "clicked", Value::from(0), 0u32)).unwrap();
+        assert!(wait_for(|| state.lock().unwrap().cfg.kb.mode == KBLightMode::Dynamic));
+    }
+}
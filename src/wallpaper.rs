@@ -0,0 +1,412 @@
+use std::{fs::{self, File}, path::{Path, PathBuf}, process::Command, sync::Mutex, thread, time::{Duration, SystemTime}};
+
+use eframe::egui;
+use image::imageops::FilterType;
+use predator_ng::{color::rgb_to_hsv, widgets::color_box::color_box};
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, KBLightMode, State, SharedState, api::percent_decode, apply_lighting, palette::{Palette, Swatch}, xdg_dir};
+
+const PALETTE_SIZE: usize = 6;
+// Images are shrunk to fit this before looking at their colors, which is plenty to find the dominant ones
+const SAMPLE_SIZE: u32 = 128;
+const KMEANS_ITERATIONS: usize = 10;
+// Colors closer than this are too alike to give to different zones, if there are others to choose
+const MIN_ZONE_DISTANCE: f32 = 60.0;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// The last extraction started from the settings, for them to show
+static EXTRACTED: Mutex<Option<Result<Extracted, String>>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Method {
+    /// Splits the colors into boxes around their medians, quick and stable
+    MedianCut,
+    /// Refines the median cut colors into the centers of the clusters they belong to
+    KMeans
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct WallpaperSettings {
+    /// Used when the wallpaper isn't detected
+    pub image: String,
+    /// Looks for the wallpaper of GNOME, KDE or pywal instead of using `image`
+    pub detect: bool,
+    pub method: Method,
+    /// Extracts the colors again and applies them whenever the image changes
+    pub watch: bool
+}
+
+impl Default for WallpaperSettings {
+    fn default() -> Self {
+        Self {
+            image: String::new(),
+            detect: true,
+            method: Method::KMeans,
+            watch: false
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Extracted {
+    path: PathBuf,
+    /// The dominant colors, most common first
+    colors: Vec<[u8; 3]>,
+    zones: [[u8; 3]; 3],
+    dynamic: [u8; 3]
+}
+
+/// Turns a `file://` URI into a path, decoding escapes such as `%20`
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.trim().trim_matches('\'').trim_start_matches("file://");
+    PathBuf::from(percent_decode(path))
+}
+
+fn gsettings(schema: &str, key: &str) -> Option<String> {
+    let output = Command::new("gsettings").args(["get", schema, key]).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn gnome_wallpaper() -> Option<PathBuf> {
+    let dark = gsettings("org.gnome.desktop.interface", "color-scheme").is_some_and(|scheme| scheme.contains("dark"));
+    let uri = if dark { gsettings("org.gnome.desktop.background", "picture-uri-dark") } else { None }
+        .or_else(|| gsettings("org.gnome.desktop.background", "picture-uri"))?;
+
+    Some(uri_to_path(&uri))
+}
+
+fn kde_wallpaper() -> Option<PathBuf> {
+    let applets = fs::read_to_string(xdg_dir("XDG_CONFIG_HOME", ".config")?.join("plasma-org.kde.plasma.desktop-appletsrc")).ok()?;
+    let uri = applets.lines().find_map(|line| line.trim().strip_prefix("Image="))?;
+
+    Some(uri_to_path(uri))
+}
+
+fn pywal_wallpaper() -> Option<PathBuf> {
+    let path = fs::read_to_string(xdg_dir("XDG_CACHE_HOME", ".cache")?.join("wal").join("wal")).ok()?;
+
+    Some(PathBuf::from(path.trim()))
+}
+
+fn detect_wallpaper() -> Option<PathBuf> {
+    [gnome_wallpaper, kde_wallpaper, pywal_wallpaper].into_iter()
+        .filter_map(|detect| detect())
+        .find(|path| path.is_file())
+}
+
+fn image_path(settings: &WallpaperSettings) -> Option<PathBuf> {
+    if settings.detect {
+        detect_wallpaper()
+    } else {
+        Some(PathBuf::from(settings.image.trim())).filter(|path| !path.as_os_str().is_empty())
+    }
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|i| (a[i] - b[i]).powi(2)).sum::<f32>().sqrt()
+}
+
+fn mean(pixels: &[[u8; 3]]) -> [f32; 3] {
+    let mut sum = [0.0; 3];
+    for pixel in pixels {
+        for (sum, channel) in sum.iter_mut().zip(pixel) {
+            *sum += *channel as f32;
+        }
+    }
+
+    sum.map(|sum| sum / pixels.len().max(1) as f32)
+}
+
+/// Keeps splitting the box with the widest channel at its median, returning the mean color of each box and its size
+fn median_cut(pixels: Vec<[u8; 3]>, count: usize) -> Vec<([f32; 3], usize)> {
+    let range = |pixels: &[[u8; 3]], channel: usize| {
+        let values = pixels.iter().map(|pixel| pixel[channel]);
+        values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+    };
+
+    let mut boxes = vec![pixels];
+    while boxes.len() < count {
+        let widest = boxes.iter().enumerate()
+            .filter(|(_, pixels)| pixels.len() > 1)
+            .map(|(i, pixels)| (i, (0..3).map(|channel| range(pixels, channel)).max().unwrap_or(0)))
+            .max_by_key(|(_, range)| *range);
+        let Some((i, widest_range)) = widest else {
+            break;
+        };
+        if widest_range == 0 {
+            break;
+        }
+
+        let mut split = boxes.swap_remove(i);
+        let channel = (0..3).max_by_key(|channel| range(&split, *channel)).unwrap();
+        split.sort_unstable_by_key(|pixel| pixel[channel]);
+        let upper = split.split_off(split.len() / 2);
+        boxes.push(split);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|pixels| (mean(pixels), pixels.len())).collect()
+}
+
+/// Starts from the median cut colors, so the result is the same every time
+fn kmeans(pixels: &[[u8; 3]], count: usize) -> Vec<([f32; 3], usize)> {
+    let mut centers: Vec<[f32; 3]> = median_cut(pixels.to_vec(), count).into_iter().map(|(center, _)| center).collect();
+    let mut sizes = vec![0; centers.len()];
+
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![[0.0; 3]; centers.len()];
+        sizes = vec![0; centers.len()];
+
+        for pixel in pixels {
+            let pixel = pixel.map(|channel| channel as f32);
+            let nearest = (0..centers.len())
+                .min_by(|a, b| distance(pixel, centers[*a]).total_cmp(&distance(pixel, centers[*b])))
+                .unwrap();
+            for (sum, channel) in sums[nearest].iter_mut().zip(pixel) {
+                *sum += channel;
+            }
+            sizes[nearest] += 1;
+        }
+
+        let mut moved = false;
+        for ((center, sum), size) in centers.iter_mut().zip(sums).zip(&sizes) {
+            if *size > 0 {
+                let new_center = sum.map(|sum| sum / *size as f32);
+                moved |= distance(*center, new_center) > 0.5;
+                *center = new_center;
+            }
+        }
+        if !moved {
+            break;
+        }
+    }
+
+    centers.into_iter().zip(sizes).filter(|(_, size)| *size > 0).collect()
+}
+
+/// The LEDs can't show dark colors, so the brightest channel is raised to full
+fn vivid(color: [u8; 3]) -> [u8; 3] {
+    let max = color.into_iter().max().unwrap_or(0);
+    if max == 0 {
+        return [255; 3];
+    }
+    color.map(|channel| (channel as f32 * 255.0 / max as f32).round() as u8)
+}
+
+/// Prefers colors that are common, saturated and bright, as dark greys look like white on the keyboard
+fn propose(colors: &[([u8; 3], usize)]) -> ([[u8; 3]; 3], [u8; 3]) {
+    let total = colors.iter().map(|(_, size)| *size).sum::<usize>().max(1) as f32;
+    let mut ranked: Vec<([u8; 3], f32)> = colors.iter().map(|(color, size)| {
+        let [_, saturation, value] = rgb_to_hsv(*color);
+        (*color, *size as f32 / total * (0.1 + saturation / 100.0) * (0.2 + value / 100.0))
+    }).collect();
+    ranked.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    let mut chosen: Vec<[u8; 3]> = Vec::new();
+    for (color, _) in &ranked {
+        let color = vivid(*color);
+        if chosen.iter().all(|other| distance(color.map(f32::from), other.map(f32::from)) >= MIN_ZONE_DISTANCE) {
+            chosen.push(color);
+        }
+    }
+    // Not enough distinct colors, so some zones share one
+    for (color, _) in ranked.iter().cycle().take(3) {
+        if chosen.len() < 3 {
+            chosen.push(vivid(*color));
+        }
+    }
+
+    let zones = [chosen[0], chosen[1], chosen[2]];
+    (zones, zones[0])
+}
+
+fn extract(path: &Path, method: Method) -> Result<Extracted, String> {
+    let image = image::open(path).map_err(|e| e.to_string())?;
+    let pixels: Vec<[u8; 3]> = image.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle).to_rgb8().pixels().map(|pixel| pixel.0).collect();
+    if pixels.is_empty() {
+        return Err("The image is empty".to_string());
+    }
+
+    let mut clusters = match method {
+        Method::MedianCut => median_cut(pixels, PALETTE_SIZE),
+        Method::KMeans => kmeans(&pixels, PALETTE_SIZE)
+    };
+    clusters.sort_by(|(_, a), (_, b)| b.cmp(a));
+    let colors: Vec<([u8; 3], usize)> = clusters.into_iter().map(|(center, size)| (center.map(|channel| channel.round() as u8), size)).collect();
+    let (zones, dynamic) = propose(&colors);
+
+    Ok(Extracted { path: path.to_path_buf(), colors: colors.into_iter().map(|(color, _)| color).collect(), zones, dynamic })
+}
+
+/// Gives the zones and the dynamic color the proposed colors, showing them if the keyboard shows either now
+fn apply(static_dev: &mut File, dynamic_dev: &mut File, cfg: &mut Config, extracted: &Extracted) {
+    for (zone, color) in cfg.kb.zones.iter_mut().zip(extracted.zones) {
+        zone.color = color;
+    }
+    cfg.kb.color = extracted.dynamic;
+    if matches!(cfg.kb.mode, KBLightMode::Static | KBLightMode::Dynamic) {
+        apply_lighting(static_dev, dynamic_dev, cfg);
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Applies the colors of the image whenever it changes, while watching is enabled
+pub fn spawn(state: SharedState) {
+    thread::spawn(move || {
+        let mut last_seen: Option<(PathBuf, Option<SystemTime>, Method)> = None;
+
+        loop {
+            let settings = state.lock().unwrap().cfg.wallpaper.clone();
+
+            if !settings.watch {
+                // Forget the image so that enabling watching applies it right away
+                last_seen = None;
+            } else if let Some(path) = image_path(&settings) {
+                let seen = (path.clone(), modified(&path), settings.method);
+                if last_seen.as_ref() != Some(&seen) {
+                    match extract(&path, settings.method) {
+                        Ok(extracted) => {
+                            let mut state = state.lock().unwrap();
+                            let State { cfg, static_dev, dynamic_dev, .. } = &mut *state;
+                            apply(static_dev, dynamic_dev, cfg, &extracted);
+                        }
+                        Err(e) => eprintln!("[ERROR]: Could not read the colors of {}: {}", path.display(), e)
+                    }
+                    last_seen = Some(seen);
+                }
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
+
+pub fn show_settings(ui: &mut egui::Ui, static_dev: &mut File, dynamic_dev: &mut File, cfg: &mut Config) -> bool {
+    let settings = &mut cfg.wallpaper;
+    let mut changed = false;
+
+    changed |= ui.checkbox(&mut settings.detect, "Use the current wallpaper")
+        .on_hover_text("Found through GNOME, KDE Plasma or pywal")
+        .changed();
+    ui.add_enabled_ui(!settings.detect, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Image");
+            changed |= ui.add(egui::TextEdit::singleline(&mut settings.image).hint_text("/path/to/image.png")).changed();
+        });
+    });
+    ui.horizontal(|ui| {
+        ui.label("Method");
+        changed |= ui.radio_value(&mut settings.method, Method::KMeans, "k-means").changed();
+        changed |= ui.radio_value(&mut settings.method, Method::MedianCut, "Median cut").changed();
+    });
+    changed |= ui.checkbox(&mut settings.watch, "Apply the colors whenever the image changes").changed();
+
+    if ui.button("Extract colors").clicked() {
+        let path = image_path(settings);
+        let method = settings.method;
+        *EXTRACTED.lock().unwrap() = None;
+        thread::spawn(move || {
+            let extracted = path.ok_or_else(|| "No wallpaper found".to_string()).and_then(|path| extract(&path, method));
+            *EXTRACTED.lock().unwrap() = Some(extracted);
+        });
+    }
+
+    let extracted = EXTRACTED.lock().unwrap().clone();
+    match extracted {
+        Some(Ok(extracted)) => {
+            let mut preview = [0; 3];
+            ui.horizontal(|ui| {
+                ui.label("Colors");
+                for color in &extracted.colors {
+                    ui.add(color_box(&mut preview, *color));
+                }
+            }).response.on_hover_text(extracted.path.display().to_string());
+            ui.horizontal(|ui| {
+                ui.label("Zones");
+                for color in extracted.zones {
+                    ui.add(color_box(&mut preview, color));
+                }
+                ui.label("Dynamic");
+                ui.add(color_box(&mut preview, extracted.dynamic));
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Apply").clicked() {
+                    apply(static_dev, dynamic_dev, cfg, &extracted);
+                    changed = true;
+                }
+                if ui.button("Save as palette").clicked() {
+                    let name = extracted.path.file_stem().map_or("Wallpaper".to_string(), |name| name.to_string_lossy().to_string());
+                    let swatches = extracted.colors.iter().map(|color| Swatch { name: String::new(), color: *color }).collect();
+                    cfg.palette.palettes.push(Palette { name, swatches });
+                    cfg.palette.selected = cfg.palette.palettes.len() - 1;
+                    changed = true;
+                }
+            });
+        }
+        Some(Err(error)) => {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        None => {}
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    fn two_colors() -> Vec<[u8; 3]> {
+        let mut pixels = vec![RED; 30];
+        pixels.extend([BLUE; 10]);
+        pixels
+    }
+
+    #[test]
+    fn decodes_file_uris() {
+        assert_eq!(uri_to_path("'file:///home/me/My%20Pictures/sunset%2B1.png'\n"), PathBuf::from("/home/me/My Pictures/sunset+1.png"));
+        assert_eq!(uri_to_path("/home/me/plain.jpg"), PathBuf::from("/home/me/plain.jpg"));
+        // Not an escape, so it's kept
+        assert_eq!(uri_to_path("file:///tmp/100%.png"), PathBuf::from("/tmp/100%.png"));
+    }
+
+    #[test]
+    fn median_cut_stops_at_the_distinct_colors() {
+        let mut pixels = vec![RED; 20];
+        pixels.extend([BLUE; 20]);
+        let mut boxes = median_cut(pixels, PALETTE_SIZE);
+        boxes.sort_by(|(a, _), (b, _)| a[0].total_cmp(&b[0]));
+        assert_eq!(boxes, vec![([0.0, 0.0, 255.0], 20), ([255.0, 0.0, 0.0], 20)]);
+
+        // Splitting at the median halves the box, even when that cuts through a color
+        let boxes = median_cut(two_colors(), 2);
+        assert_eq!(boxes, vec![([255.0, 0.0, 0.0], 20), ([127.5, 0.0, 127.5], 20)]);
+    }
+
+    #[test]
+    fn kmeans_moves_to_the_clusters() {
+        let mut clusters = kmeans(&two_colors(), 2);
+        clusters.sort_by_key(|(_, size)| *size);
+        assert_eq!(clusters, vec![([0.0, 0.0, 255.0], 10), ([255.0, 0.0, 0.0], 30)]);
+    }
+
+    #[test]
+    fn proposes_distinct_vivid_colors() {
+        let colors = [(RED, 50), ([40, 40, 40], 40), ([0, 0, 128], 10), ([250, 5, 5], 10)];
+        let (zones, dynamic) = propose(&colors);
+        assert_eq!(zones, [RED, BLUE, [255; 3]]);
+        assert_eq!(dynamic, RED);
+
+        // Zones share the colors there are
+        assert_eq!(propose(&[(RED, 1)]), ([RED; 3], RED));
+    }
+}
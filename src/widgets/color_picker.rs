@@ -0,0 +1,213 @@
+use crate::color::{hsl_to_rgb, hsv_to_rgb, oklch_to_rgb, rgb_to_hsl, rgb_to_hsv, rgb_to_oklch};
+
+// Where the copied color is kept, shared by every picker in the window
+const CLIPBOARD_ID: &str = "color_picker_clipboard";
+
+#[derive(Clone, Copy, PartialEq, Default)]
+enum Model {
+    #[default] Hsv,
+    Hsl,
+    Oklch
+}
+
+impl Model {
+    const ALL: [Model; 3] = [Model::Hsv, Model::Hsl, Model::Oklch];
+
+    fn name(self) -> &'static str {
+        match self {
+            Model::Hsv => "HSV",
+            Model::Hsl => "HSL",
+            Model::Oklch => "OKLCH"
+        }
+    }
+
+    fn channels(self) -> [(&'static str, std::ops::RangeInclusive<f32>); 3] {
+        match self {
+            Model::Hsv => [("H", 0.0..=360.0), ("S", 0.0..=100.0), ("V", 0.0..=100.0)],
+            Model::Hsl => [("H", 0.0..=360.0), ("S", 0.0..=100.0), ("L", 0.0..=100.0)],
+            // Chroma stays below 0.37 for every sRGB color
+            Model::Oklch => [("L", 0.0..=100.0), ("C", 0.0..=0.37), ("H", 0.0..=360.0)]
+        }
+    }
+
+    fn values(self, rgb: [u8; 3]) -> [f32; 3] {
+        match self {
+            Model::Hsv => rgb_to_hsv(rgb),
+            Model::Hsl => rgb_to_hsl(rgb),
+            Model::Oklch => rgb_to_oklch(rgb)
+        }
+    }
+
+    fn rgb(self, values: [f32; 3]) -> [u8; 3] {
+        match self {
+            Model::Hsv => hsv_to_rgb(values),
+            Model::Hsl => hsl_to_rgb(values),
+            Model::Oklch => oklch_to_rgb(values)
+        }
+    }
+}
+
+/// Kept between frames, so the hue isn't lost while the saturation is 0 and typing a hex code isn't interrupted
+#[derive(Clone)]
+struct PickerState {
+    model: Model,
+    values: [f32; 3],
+    // The color `values` were made from, to notice when the color is changed from outside of the picker
+    rgb: [u8; 3],
+    hex: String
+}
+
+impl PickerState {
+    fn new(rgb: [u8; 3]) -> Self {
+        let model = Model::default();
+        Self { model, values: model.values(rgb), rgb, hex: to_hex(rgb) }
+    }
+
+    fn set_rgb(&mut self, rgb: [u8; 3]) {
+        self.values = self.model.values(rgb);
+        self.rgb = rgb;
+        self.hex = to_hex(rgb);
+    }
+}
+
+fn to_hex(rgb: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
+}
+
+/// Parses `#rrggbb`, the `#` is optional
+pub fn parse_hex(hex: &str) -> Option<[u8; 3]> {
+    let hex = hex.trim();
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn picker_contents(ui: &mut egui::Ui, color: &mut [u8; 3], state: &mut PickerState) -> bool {
+    let mut changed = false;
+
+    ui.horizontal(|ui| {
+        for model in Model::ALL {
+            if ui.selectable_label(state.model == model, model.name()).clicked() {
+                state.model = model;
+                state.values = model.values(*color);
+            }
+        }
+    });
+
+    egui::Grid::new("channels").num_columns(2).show(ui, |ui| {
+        for ((name, range), value) in state.model.channels().into_iter().zip(state.values.iter_mut()) {
+            ui.label(name);
+            let decimals = if *range.end() < 1.0 { 3 } else { 0 };
+            if ui.add(egui::Slider::new(value, range).fixed_decimals(decimals)).changed() {
+                changed = true;
+            }
+            ui.end_row();
+        }
+    });
+    if changed {
+        *color = state.model.rgb(state.values);
+        state.rgb = *color;
+        state.hex = to_hex(*color);
+    }
+
+    ui.horizontal(|ui| {
+        let parsed = parse_hex(&state.hex);
+        let mut hex_edit = egui::TextEdit::singleline(&mut state.hex).desired_width(70.0).font(egui::TextStyle::Monospace);
+        if parsed.is_none() {
+            hex_edit = hex_edit.text_color(ui.visuals().error_fg_color);
+        }
+        let hex_response = ui.add(hex_edit).on_hover_text("#rrggbb");
+        if hex_response.changed() {
+            if let Some(rgb) = parse_hex(&state.hex) {
+                *color = rgb;
+                state.values = state.model.values(rgb);
+                state.rgb = rgb;
+                changed = true;
+            }
+        }
+        if hex_response.lost_focus() && parsed.is_none() {
+            state.hex = to_hex(*color);
+        }
+
+        let clipboard_id = egui::Id::new(CLIPBOARD_ID);
+        if ui.button("Copy").clicked() {
+            ui.data_mut(|data| data.insert_temp(clipboard_id, *color));
+            ui.ctx().output_mut(|output| output.copied_text = to_hex(*color));
+        }
+        let copied = ui.data(|data| data.get_temp::<[u8; 3]>(clipboard_id));
+        let mut pasted = None;
+        if ui.add_enabled(copied.is_some(), egui::Button::new("Paste"))
+            .on_hover_text("Ctrl+V pastes a hex code from anywhere")
+            .clicked() {
+            pasted = copied;
+        }
+
+        // The system clipboard only arrives with Ctrl+V, which the hex field handles itself while it's focused
+        if !hex_response.has_focus() {
+            let from_clipboard = ui.input(|input| input.events.iter().rev().find_map(|event| match event {
+                egui::Event::Paste(text) => parse_hex(text),
+                _ => None
+            }));
+            pasted = from_clipboard.or(pasted);
+        }
+
+        if let Some(rgb) = pasted {
+            *color = rgb;
+            state.set_rgb(rgb);
+            changed = true;
+        }
+    });
+
+    changed
+}
+
+fn color_picker_ui(ui: &mut egui::Ui, color: &mut [u8; 3]) -> egui::Response {
+    let desired_size = ui.spacing().interact_size.y * egui::vec2(2.0, 1.0);
+    let (rect, mut response) = ui.allocate_exact_size(desired_size, egui::Sense::click());
+    let popup_id = response.id.with("popup");
+
+    if ui.is_rect_visible(rect) {
+        let visuals = ui.style().interact(&response);
+        let rect = rect.expand(visuals.expansion);
+        ui.painter().rect(rect, visuals.rounding, egui::Color32::from_rgb(color[0], color[1], color[2]), visuals.fg_stroke);
+    }
+
+    if response.clicked() {
+        ui.memory_mut(|memory| memory.toggle_popup(popup_id));
+    }
+    if !ui.memory(|memory| memory.is_popup_open(popup_id)) {
+        return response;
+    }
+
+    let mut state = ui.data(|data| data.get_temp::<PickerState>(popup_id)).unwrap_or_else(|| PickerState::new(*color));
+    if state.rgb != *color {
+        state.set_rgb(*color);
+    }
+
+    let area = egui::Area::new(popup_id)
+        .order(egui::Order::Foreground)
+        .fixed_pos(rect.left_bottom())
+        .constrain(true)
+        .show(ui.ctx(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| picker_contents(ui, color, &mut state)).inner
+        });
+    if area.inner {
+        response.mark_changed();
+    }
+    ui.data_mut(|data| data.insert_temp(popup_id, state));
+
+    if ui.input(|input| input.key_pressed(egui::Key::Escape)) || (area.response.clicked_elsewhere() && !response.clicked()) {
+        ui.memory_mut(|memory| memory.close_popup());
+    }
+
+    response
+}
+
+// Opens HSV, HSL and OKLCH sliders, a hex field and copy/paste buttons below a swatch: `ui.add(color_picker(&mut color))`
+pub fn color_picker(color: &mut [u8; 3]) -> impl egui::Widget + '_ {
+    move |ui: &mut egui::Ui| color_picker_ui(ui, color)
+}
@@ -0,0 +1,166 @@
+use std::f32::consts::TAU;
+
+use crate::color::hsv_to_rgb;
+
+const ZONES: usize = 3;
+// How many key widths a row spans
+const ROW_UNITS: f32 = 15.0;
+const MAX_WIDTH: f32 = 420.0;
+// Animations don't need to be smoother than this
+const FRAME_TIME: std::time::Duration = std::time::Duration::from_millis(33);
+
+// The key widths of each row, a laptop keyboard without a numpad
+const ROWS: [&[f32]; 6] = [
+    &[1.0; 15],
+    &[1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 2.0],
+    &[1.5, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.5],
+    &[1.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 2.25],
+    &[2.25, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 2.75],
+    &[1.25, 1.0, 1.25, 1.25, 4.75, 1.25, 1.25, 1.0, 1.0, 1.0]
+];
+
+/// The firmware effects, as far as they can be imitated
+#[derive(Clone, Copy, PartialEq)]
+pub enum Effect {
+    Breathing,
+    Neon,
+    Wave,
+    Shifting,
+    Zoom,
+    Meteor,
+    Twinkling
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    LeftToRight,
+    RightToLeft
+}
+
+/// What the keyboard is lit with
+#[derive(Clone, Copy, PartialEq)]
+pub enum Lighting {
+    /// The color of each zone from left to right, `None` when the zone is off
+    Static([Option<[u8; 3]>; ZONES]),
+    /// An effect at `speed`, which goes from `min_speed` to `max_speed` on the model being shown
+    Dynamic { effect: Effect, speed: u8, min_speed: u8, max_speed: u8, direction: Direction, color: [u8; 3] }
+}
+
+/// Where a key is, with `x` and `y` from 0 to 1 across the keyboard
+struct Key {
+    rect: egui::Rect,
+    x: f32,
+    y: f32,
+    index: usize
+}
+
+fn hue(hue: f32) -> [u8; 3] {
+    hsv_to_rgb([hue.rem_euclid(1.0) * 360.0, 100.0, 100.0])
+}
+
+fn scale(color: [u8; 3], amount: f32) -> [u8; 3] {
+    color.map(|channel| (channel as f32 * amount.clamp(0.0, 1.0)).round() as u8)
+}
+
+// Spreads the key index and the step of an animation into a number from 0 to 1
+fn noise(index: usize, step: u32) -> f32 {
+    let mut hash = (index as u32).wrapping_mul(0x9E37_79B9) ^ step.wrapping_mul(0x85EB_CA6B);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x2C1B_3C6D);
+    hash ^= hash >> 12;
+    (hash & 0xFFFF) as f32 / 0xFFFF as f32
+}
+
+/// The color of a key at `time` seconds, `None` when it's dark
+fn key_color(lighting: Lighting, key: &Key, time: f32) -> Option<[u8; 3]> {
+    let (effect, speed, min_speed, max_speed, direction, color) = match lighting {
+        Lighting::Static(zones) => return zones[((key.x * ZONES as f32) as usize).min(ZONES - 1)],
+        Lighting::Dynamic { effect, speed, min_speed, max_speed, direction, color } => (effect, speed, min_speed, max_speed, direction, color)
+    };
+
+    // Faster speeds go through the effect more times a second, the fastest one a cycle a second
+    let max_speed = max_speed.max(min_speed);
+    let steps = (max_speed - min_speed) as f32 + 1.0;
+    let cycles = time * (speed.clamp(min_speed, max_speed) - min_speed + 1) as f32 / steps;
+    let along = match direction {
+        Direction::LeftToRight => key.x,
+        Direction::RightToLeft => 1.0 - key.x
+    };
+
+    let color = match effect {
+        Effect::Breathing => scale(color, 0.5 - 0.5 * (cycles * TAU).cos()),
+        Effect::Neon => hue(cycles * 0.25),
+        Effect::Wave => hue(cycles * 0.5 - along),
+        // Bands of a third of the keyboard, each a step further around the hue circle
+        Effect::Shifting => hue(((cycles * 0.5 - along) * 3.0).floor() / 6.0),
+        Effect::Zoom => {
+            let distance = (key.x - 0.5).hypot((key.y - 0.5) * 0.4) * 2.0;
+            scale(color, 1.0 - ((distance - cycles).rem_euclid(1.0) * 3.0))
+        }
+        Effect::Meteor => {
+            // Every row has its meteor at a different point
+            let head = (cycles * 0.75 + noise(0, (key.y * 8.0) as u32)).rem_euclid(1.0) * 1.4;
+            let tail = head - along;
+            if (0.0..0.4).contains(&tail) { scale(color, 1.0 - tail / 0.4) } else { [0; 3] }
+        }
+        Effect::Twinkling => {
+            let step = (cycles * 4.0) as u32;
+            let fade = 1.0 - (cycles * 4.0).fract();
+            if noise(key.index, step) < 0.15 { scale(color, fade) } else { [0; 3] }
+        }
+    };
+
+    Some(color)
+}
+
+fn keyboard_ui(ui: &mut egui::Ui, lighting: Lighting, brightness: u8) -> egui::Response {
+    let width = ui.available_width().min(MAX_WIDTH);
+    let unit = width / ROW_UNITS;
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(width, unit * ROWS.len() as f32), egui::Sense::hover());
+
+    if ui.is_rect_visible(rect) {
+        let time = ui.input(|input| input.time) as f32;
+        if matches!(lighting, Lighting::Dynamic { .. }) {
+            ui.ctx().request_repaint_after(FRAME_TIME);
+        }
+
+        let painter = ui.painter_at(rect);
+        let off = ui.visuals().widgets.noninteractive.bg_stroke.color;
+        let gap = (unit * 0.1).max(1.0);
+        let mut index = 0;
+
+        for (row, widths) in ROWS.iter().enumerate() {
+            let mut left = 0.0;
+            for width in widths.iter() {
+                let min = rect.min + egui::vec2(left * unit, row as f32 * unit);
+                let key = Key {
+                    rect: egui::Rect::from_min_size(min, egui::vec2(width * unit, unit)).shrink(gap / 2.0),
+                    x: (left + width / 2.0) / ROW_UNITS,
+                    y: (row as f32 + 0.5) / ROWS.len() as f32,
+                    index
+                };
+
+                let stroke = match key_color(lighting, &key, time) {
+                    Some(color) => {
+                        let [r, g, b] = scale(color, brightness as f32 / 100.0);
+                        let lit = egui::Color32::from_rgb(r, g, b);
+                        painter.rect_filled(key.rect, gap, lit.linear_multiply(0.25));
+                        egui::Stroke::new(gap.min(2.0), lit)
+                    }
+                    None => egui::Stroke::new(gap.min(2.0), off)
+                };
+                painter.rect_stroke(key.rect, gap, stroke);
+
+                left += width;
+                index += 1;
+            }
+        }
+    }
+
+    response
+}
+
+/// Draws the keyboard lit the way `lighting` lights it, at a brightness from 0 to 100
+pub fn keyboard(lighting: Lighting, brightness: u8) -> impl egui::Widget {
+    move |ui: &mut egui::Ui| keyboard_ui(ui, lighting, brightness)
+}
@@ -1,2 +1,5 @@
 pub mod toggle;
 pub mod color_box;
+pub mod color_picker;
+pub mod keyboard;
+pub mod zone_editor;
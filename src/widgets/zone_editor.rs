@@ -0,0 +1,44 @@
+use super::{color_picker::color_picker, toggle::toggle};
+
+fn rgb_values_ui(ui: &mut egui::Ui, color: &mut [u8; 3]) -> egui::Response {
+    let mut changed = false;
+    let mut response = ui.horizontal(|ui| {
+        for (channel, name) in color.iter_mut().zip(["R", "G", "B"]) {
+            let label = ui.label(name);
+            changed |= ui.add(egui::DragValue::new(channel)).labelled_by(label.id).changed();
+        }
+    }).response;
+
+    if changed {
+        response.mark_changed();
+    }
+    response
+}
+
+// Edits each channel of a color with a drag value: `ui.add(rgb_values(&mut color))`
+pub fn rgb_values(color: &mut [u8; 3]) -> impl egui::Widget + '_ {
+    move |ui: &mut egui::Ui| rgb_values_ui(ui, color)
+}
+
+fn zone_editor_ui(ui: &mut egui::Ui, label: &str, enabled: &mut bool, color: &mut [u8; 3]) -> egui::Response {
+    let mut changed = false;
+    let mut response = ui.vertical(|ui| {
+        ui.label(label);
+        ui.horizontal_wrapped(|ui| {
+            changed |= ui.add(toggle(enabled)).changed();
+            ui.add_enabled_ui(*enabled, |ui| changed |= ui.add(color_picker(color)).changed());
+        });
+        ui.add_space(10.0);
+        ui.add_enabled_ui(*enabled, |ui| changed |= ui.add(rgb_values(color)).changed());
+    }).response;
+
+    if changed {
+        response.mark_changed();
+    }
+    response
+}
+
+// A zone's on/off toggle, color picker and channels under a label: `ui.add(zone_editor("Zone 1", &mut enabled, &mut color))`
+pub fn zone_editor<'a>(label: &'a str, enabled: &'a mut bool, color: &'a mut [u8; 3]) -> impl egui::Widget + 'a {
+    move |ui: &mut egui::Ui| zone_editor_ui(ui, label, enabled, color)
+}